@@ -0,0 +1,84 @@
+//! Make/Ninja dependency files listing the sources of an output
+
+use std::path::{Path, PathBuf};
+
+/// Dependency file (`output.sh.d`) in Makefile rule syntax
+///
+/// Rendered as `target: dep1 dep2 ...`, the format `gcc -MD` writes and
+/// both Make (`-include`) and Ninja (`depfile =`) read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Depfile {
+    /// Output file the rule builds
+    pub target: PathBuf,
+    /// Files the output was generated from
+    pub dependencies: Vec<PathBuf>,
+}
+
+impl Depfile {
+    /// Create dependency file
+    pub fn new(target: impl Into<PathBuf>, dependencies: Vec<PathBuf>) -> Self {
+        Self { target: target.into(), dependencies }
+    }
+    
+    /// Default path for output file (`output.sh` -> `output.sh.d`)
+    pub fn path_for(output_path: &Path) -> PathBuf {
+        let mut path = output_path.as_os_str().to_os_string();
+        path.push(".d");
+        PathBuf::from(path)
+    }
+    
+    /// Render as Makefile rule, one dependency per line
+    pub fn render(&self) -> String {
+        let mut rule = format!("{}:", escape_path(&self.target));
+        for dependency in &self.dependencies {
+            rule.push_str(" \\\n  ");
+            rule.push_str(&escape_path(dependency));
+        }
+        rule.push('\n');
+        rule
+    }
+}
+
+/// Escape path for Makefile rules (spaces, `#` and `$`)
+fn escape_path(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    let mut escaped = String::with_capacity(path.len());
+    
+    for c in path.chars() {
+        match c {
+            ' ' | '\t' | '#' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '$' => escaped.push_str("$$"),
+            _ => escaped.push(c),
+        }
+    }
+    
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_render_depfile() {
+        let depfile = Depfile::new("dist/deploy.sh", vec![
+            PathBuf::from("/src/main.sh"),
+            PathBuf::from("/src/lib/my lib.sh"),
+            PathBuf::from("/src/lib/#1 $HOME.sh"),
+        ]);
+        
+        assert_eq!(
+            depfile.render(),
+            "dist/deploy.sh: \\\n  /src/main.sh \\\n  /src/lib/my\\ lib.sh \\\n  /src/lib/\\#1\\ $$HOME.sh\n"
+        );
+        assert_eq!(Depfile::new("out.sh", Vec::new()).render(), "out.sh:\n");
+    }
+    
+    #[test]
+    fn test_depfile_path() {
+        assert_eq!(Depfile::path_for(Path::new("dist/deploy.sh")), PathBuf::from("dist/deploy.sh.d"));
+    }
+}
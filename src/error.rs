@@ -2,6 +2,8 @@
 
 use std::path::PathBuf;
 
+use crate::resolver::SearchCandidate;
+
 /// Preprocessor error types
 #[derive(Debug, thiserror::Error)]
 pub enum PreprocessorError {
@@ -9,6 +11,72 @@ pub enum PreprocessorError {
     #[error("File not found: {path}")]
     FileNotFound { path: PathBuf },
     
+    /// Include directive could not be resolved
+    #[error(
+        "Include file not found: {file_path}\n  --> {}:{line_number}: {directive}\n  searched:{}{}",
+        including_file.display(),
+        format_candidates(.searched),
+        format_suggestion(.suggestion)
+    )]
+    IncludeNotFound {
+        file_path: String,
+        directive: String,
+        including_file: PathBuf,
+        line_number: usize,
+        searched: Vec<SearchCandidate>,
+        suggestion: Option<String>,
+    },
+    
+    /// Include resolved outside the allowed root directory
+    #[error(
+        "Include escapes root directory {}: {}\n  --> {}:{line_number}: {directive}",
+        root.display(),
+        path.display(),
+        including_file.display()
+    )]
+    PathOutsideRoot {
+        path: PathBuf,
+        root: PathBuf,
+        directive: String,
+        including_file: PathBuf,
+        line_number: usize,
+    },
+    
+    /// Include reached through a symbolic link refused by the symlink policy
+    #[error(
+        "Symbolic link refused ({reason}): {}\n  --> {}:{line_number}: {directive}\n  link chain:{}",
+        path.display(),
+        including_file.display(),
+        format_chain(.chain)
+    )]
+    SymlinkRefused {
+        path: PathBuf,
+        reason: String,
+        chain: Vec<(PathBuf, PathBuf)>,
+        directive: String,
+        including_file: PathBuf,
+        line_number: usize,
+    },
+    
+    /// Included file's interpreter differs from the root script's
+    #[error(
+        "Shebang conflict: {} uses {interpreter}, root script uses {root_interpreter}\n  --> {}:{line_number}: {directive}",
+        path.display(),
+        including_file.display()
+    )]
+    ShebangConflict {
+        path: PathBuf,
+        interpreter: String,
+        root_interpreter: String,
+        directive: String,
+        including_file: PathBuf,
+        line_number: usize,
+    },
+    
+    /// File content not valid in its encoding
+    #[error("Cannot decode {} as {encoding} (use --input-encoding or a `coding:` comment for other encodings)", path.display())]
+    InvalidEncoding { path: PathBuf, encoding: String },
+    
     /// IO error
     #[error("IO error: {0}")]
     IoError(#[from] std::io::Error),
@@ -20,6 +88,13 @@ pub enum PreprocessorError {
         stack: String,
     },
     
+    /// File included more than once (duplicate include policy is error)
+    #[error("Duplicate include: {path} (include stack: {stack})")]
+    DuplicateInclude {
+        path: PathBuf,
+        stack: String,
+    },
+    
     /// Invalid include directive
     #[error("Invalid include directive at line {line_number}: {directive}")]
     InvalidIncludeDirective {
@@ -27,6 +102,47 @@ pub enum PreprocessorError {
         directive: String,
     },
     
+    /// Invalid preprocessor directive
+    #[error("Invalid directive at {path}:{line_number}: {directive}")]
+    InvalidDirective {
+        path: PathBuf,
+        line_number: usize,
+        directive: String,
+    },
+    
+    /// Invalid or failing `#if` / `#elif` expression
+    #[error("Invalid expression at {path}:{line_number}: {directive} ({message})")]
+    InvalidExpression {
+        path: PathBuf,
+        line_number: usize,
+        directive: String,
+        message: String,
+    },
+    
+    /// Macro invocation could not be expanded
+    #[error("Macro expansion failed at {path}:{line_number}: {message}")]
+    MacroExpansion {
+        path: PathBuf,
+        line_number: usize,
+        message: String,
+    },
+    
+    /// Conditional block not closed before end of file
+    #[error("Unterminated conditional at {path}:{line_number}: {directive} (missing #endif)")]
+    UnterminatedConditional {
+        path: PathBuf,
+        line_number: usize,
+        directive: String,
+    },
+    
+    /// `#elif` / `#else` / `#endif` without matching conditional block
+    #[error("Unmatched conditional directive at {path}:{line_number}: {directive}")]
+    UnmatchedConditional {
+        path: PathBuf,
+        line_number: usize,
+        directive: String,
+    },
+    
     /// Max include depth exceeded
     #[error("Maximum include depth ({max_depth}) exceeded at: {path}")]
     MaxDepthExceeded { path: PathBuf, max_depth: usize },
@@ -36,5 +152,26 @@ pub enum PreprocessorError {
     PermissionDenied { path: PathBuf },
 }
 
+/// Format searched candidates in search order, one per line
+fn format_candidates(candidates: &[SearchCandidate]) -> String {
+    candidates.iter()
+        .map(|candidate| format!("\n    {} [{}]", candidate.path.display(), candidate.origin))
+        .collect()
+}
+
+/// Format symbolic link hops, one per line
+fn format_chain(chain: &[(PathBuf, PathBuf)]) -> String {
+    chain.iter()
+        .map(|(link, target)| format!("\n    {} -> {}", link.display(), target.display()))
+        .collect()
+}
+
+/// Format "did you mean" hint
+fn format_suggestion(suggestion: &Option<String>) -> String {
+    suggestion.as_ref()
+        .map(|suggestion| format!("\n  did you mean: {}?", suggestion))
+        .unwrap_or_default()
+}
+
 /// Result type for preprocessor operations
 pub type Result<T> = anyhow::Result<T>;
\ No newline at end of file
@@ -0,0 +1,400 @@
+//! Conditional expression parser and evaluator (`#if` / `#elif`)
+
+use std::fmt;
+
+/// Expression value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// Integer value
+    Int(i64),
+    /// String value
+    Str(String),
+}
+
+impl Value {
+    /// Convert symbol text to value (integers are parsed)
+    fn from_symbol(text: &str) -> Self {
+        match text.trim().parse::<i64>() {
+            Ok(n) => Value::Int(n),
+            Err(_) => Value::Str(text.to_string()),
+        }
+    }
+    
+    /// Truthiness (non-zero integer or non-empty string)
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Int(n) => *n != 0,
+            Value::Str(s) => !s.is_empty(),
+        }
+    }
+    
+    /// Integer value for ordering comparisons
+    fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Int(n) => Some(*n),
+            Value::Str(s) => s.trim().parse().ok(),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Str(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// Binary operators
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    /// `==`
+    Eq,
+    /// `!=`
+    Ne,
+    /// `<`
+    Lt,
+    /// `<=`
+    Le,
+    /// `>`
+    Gt,
+    /// `>=`
+    Ge,
+    /// `&&`
+    And,
+    /// `||`
+    Or,
+}
+
+impl BinaryOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+        }
+    }
+}
+
+/// Parsed conditional expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    /// Integer or string literal
+    Literal(Value),
+    /// Symbol reference (undefined symbols evaluate to 0)
+    Symbol(String),
+    /// `defined(NAME)` / `defined NAME`
+    Defined(String),
+    /// `!expr`
+    Not(Box<Expr>),
+    /// Binary operation
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// Parse expression text
+    pub fn parse(input: &str) -> Result<Expr, String> {
+        let tokens = tokenize(input)?;
+        let mut parser = ExprParser { tokens, position: 0 };
+        
+        let expr = parser.parse_or()?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("unexpected '{}'", token));
+        }
+        
+        Ok(expr)
+    }
+    
+    /// Evaluate expression against symbol lookup
+    pub fn evaluate<'a>(&self, lookup: &dyn Fn(&str) -> Option<&'a str>) -> Result<Value, String> {
+        match self {
+            Expr::Literal(value) => Ok(value.clone()),
+            Expr::Symbol(name) => Ok(lookup(name).map_or(Value::Int(0), Value::from_symbol)),
+            Expr::Defined(name) => Ok(Value::Int(lookup(name).is_some() as i64)),
+            Expr::Not(inner) => Ok(Value::Int(!inner.evaluate(lookup)?.is_truthy() as i64)),
+            Expr::Binary(op, left, right) => {
+                // Short-circuit boolean operators
+                let left = left.evaluate(lookup)?;
+                let result = match op {
+                    BinaryOp::And => left.is_truthy() && right.evaluate(lookup)?.is_truthy(),
+                    BinaryOp::Or => left.is_truthy() || right.evaluate(lookup)?.is_truthy(),
+                    BinaryOp::Eq | BinaryOp::Ne => {
+                        let right = right.evaluate(lookup)?;
+                        let equal = match (&left, &right) {
+                            (Value::Int(a), Value::Int(b)) => a == b,
+                            _ => left.to_string() == right.to_string(),
+                        };
+                        equal == (*op == BinaryOp::Eq)
+                    }
+                    _ => {
+                        let right = right.evaluate(lookup)?;
+                        let (Some(a), Some(b)) = (left.as_int(), right.as_int()) else {
+                            return Err(format!(
+                                "operator '{}' requires integers, got '{}' and '{}'",
+                                op.symbol(), left, right
+                            ));
+                        };
+                        match op {
+                            BinaryOp::Lt => a < b,
+                            BinaryOp::Le => a <= b,
+                            BinaryOp::Gt => a > b,
+                            _ => a >= b,
+                        }
+                    }
+                };
+                Ok(Value::Int(result as i64))
+            }
+        }
+    }
+}
+
+/// Expression token
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Int(i64),
+    Str(String),
+    Op(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "{}", name),
+            Token::Int(n) => write!(f, "{}", n),
+            Token::Str(s) => write!(f, "\"{}\"", s),
+            Token::Op(op) => write!(f, "{}", op),
+        }
+    }
+}
+
+/// Split expression into tokens
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    // Longer operators first so `<=` is not read as `<`
+    const OPERATORS: [&str; 11] = ["==", "!=", "<=", ">=", "&&", "||", "<", ">", "!", "(", ")"];
+    
+    let mut tokens = Vec::new();
+    let mut rest = input.trim_start();
+    
+    while let Some(c) = rest.chars().next() {
+        if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else if c.is_ascii_digit() || (c == '-' && rest[1..].starts_with(|c: char| c.is_ascii_digit())) {
+            let end = rest[1..].find(|c: char| !c.is_ascii_digit()).map_or(rest.len(), |i| i + 1);
+            let number = rest[..end].parse().map_err(|_| format!("invalid integer '{}'", &rest[..end]))?;
+            tokens.push(Token::Int(number));
+            rest = &rest[end..];
+        } else if c == '"' || c == '\'' {
+            let (literal, remaining) = read_string(rest, c)?;
+            tokens.push(Token::Str(literal));
+            rest = remaining;
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else {
+            return Err(format!("unexpected character '{}'", c));
+        }
+        rest = rest.trim_start();
+    }
+    
+    Ok(tokens)
+}
+
+/// Read quoted string literal, returning contents and remaining input
+fn read_string(input: &str, quote: char) -> Result<(String, &str), String> {
+    let mut literal = String::new();
+    let mut chars = input.char_indices().skip(1);
+    
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some((_, escaped)) => literal.push(escaped),
+                None => break,
+            },
+            c if c == quote => return Ok((literal, &input[index + 1..])),
+            c => literal.push(c),
+        }
+    }
+    
+    Err(format!("unterminated string {}", input))
+}
+
+/// Recursive descent parser over tokens
+struct ExprParser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl ExprParser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+    
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+    
+    /// Consume operator if next
+    fn eat(&mut self, op: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Op(next)) if *next == op) {
+            self.position += 1;
+            return true;
+        }
+        false
+    }
+    
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        if self.eat(op) {
+            return Ok(());
+        }
+        match self.peek() {
+            Some(token) => Err(format!("expected '{}', found '{}'", op, token)),
+            None => Err(format!("expected '{}' at end of expression", op)),
+        }
+    }
+    
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_and()?;
+        while self.eat("||") {
+            left = Expr::Binary(BinaryOp::Or, Box::new(left), Box::new(self.parse_and()?));
+        }
+        Ok(left)
+    }
+    
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_equality()?;
+        while self.eat("&&") {
+            left = Expr::Binary(BinaryOp::And, Box::new(left), Box::new(self.parse_equality()?));
+        }
+        Ok(left)
+    }
+    
+    fn parse_equality(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_relational()?;
+        loop {
+            let op = if self.eat("==") {
+                BinaryOp::Eq
+            } else if self.eat("!=") {
+                BinaryOp::Ne
+            } else {
+                return Ok(left);
+            };
+            left = Expr::Binary(op, Box::new(left), Box::new(self.parse_relational()?));
+        }
+    }
+    
+    fn parse_relational(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_unary()?;
+        loop {
+            let op = if self.eat("<=") {
+                BinaryOp::Le
+            } else if self.eat(">=") {
+                BinaryOp::Ge
+            } else if self.eat("<") {
+                BinaryOp::Lt
+            } else if self.eat(">") {
+                BinaryOp::Gt
+            } else {
+                return Ok(left);
+            };
+            left = Expr::Binary(op, Box::new(left), Box::new(self.parse_unary()?));
+        }
+    }
+    
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.parse_unary()?)));
+        }
+        self.parse_primary()
+    }
+    
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Op("(")) => {
+                let expr = self.parse_or()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Some(Token::Ident(name)) if name == "defined" => {
+                let parenthesized = self.eat("(");
+                let name = match self.next() {
+                    Some(Token::Ident(name)) => name,
+                    _ => return Err("expected symbol name after 'defined'".to_string()),
+                };
+                if parenthesized {
+                    self.expect(")")?;
+                }
+                Ok(Expr::Defined(name))
+            }
+            Some(Token::Ident(name)) => Ok(Expr::Symbol(name)),
+            Some(Token::Int(n)) => Ok(Expr::Literal(Value::Int(n))),
+            Some(Token::Str(s)) => Ok(Expr::Literal(Value::Str(s))),
+            Some(token) => Err(format!("unexpected '{}'", token)),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn eval(input: &str) -> Result<bool, String> {
+        let lookup = |name: &str| match name {
+            "TARGET" => Some("alpine"),
+            "VERSION" => Some("3"),
+            "EMPTY" => Some(""),
+            _ => None,
+        };
+        Ok(Expr::parse(input)?.evaluate(&lookup)?.is_truthy())
+    }
+    
+    #[test]
+    fn test_defined() {
+        assert_eq!(eval("defined(TARGET)"), Ok(true));
+        assert_eq!(eval("defined EMPTY"), Ok(true));
+        assert_eq!(eval("!defined(MISSING)"), Ok(true));
+        assert_eq!(eval("EMPTY"), Ok(false));
+        assert_eq!(eval("MISSING"), Ok(false));
+    }
+    
+    #[test]
+    fn test_comparisons() {
+        assert_eq!(eval("TARGET == \"alpine\""), Ok(true));
+        assert_eq!(eval("TARGET != 'alpine'"), Ok(false));
+        assert_eq!(eval("VERSION >= 3"), Ok(true));
+        assert_eq!(eval("VERSION < 3"), Ok(false));
+        assert_eq!(eval("VERSION == \"3\""), Ok(true));
+        assert_eq!(eval("MISSING == 0"), Ok(true));
+        assert_eq!(eval("-1 < 0"), Ok(true));
+    }
+    
+    #[test]
+    fn test_boolean_logic() {
+        assert_eq!(eval("TARGET == \"debian\" || VERSION > 2 && defined(TARGET)"), Ok(true));
+        assert_eq!(eval("(TARGET == \"debian\" || VERSION > 2) && !defined(TARGET)"), Ok(false));
+        assert_eq!(eval("!!1"), Ok(true));
+        // Right side of short-circuit is not evaluated
+        assert_eq!(eval("0 && TARGET > 1"), Ok(false));
+    }
+    
+    #[test]
+    fn test_errors() {
+        assert!(eval("TARGET > 1").is_err());
+        assert!(eval("(VERSION").is_err());
+        assert!(eval("VERSION ==").is_err());
+        assert!(eval("\"open").is_err());
+        assert!(eval("VERSION = 3").is_err());
+        assert!(eval("").is_err());
+    }
+}
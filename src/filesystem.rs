@@ -0,0 +1,303 @@
+//! Filesystem access for include resolution
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Filesystem operations used to resolve and read includes
+pub trait FileSystem: fmt::Debug + Send + Sync {
+    /// Read file content
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+    
+    /// Check if path is an existing file (following symbolic links)
+    fn is_file(&self, path: &Path) -> bool;
+    
+    /// Absolute path with `.`, `..` and symbolic links resolved
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+    
+    /// Check if path itself is a symbolic link
+    fn is_symlink(&self, path: &Path) -> bool;
+    
+    /// Target of symbolic link
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf>;
+    
+    /// Paths of directory entries
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+    
+    /// Unix permission bits of file (`None` if unknown or unsupported)
+    fn mode(&self, path: &Path) -> Option<u32>;
+}
+
+/// Filesystem on disk
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealFileSystem;
+
+impl FileSystem for RealFileSystem {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+    
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+    
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        path.canonicalize()
+    }
+    
+    fn is_symlink(&self, path: &Path) -> bool {
+        fs::symlink_metadata(path)
+            .map(|metadata| metadata.file_type().is_symlink())
+            .unwrap_or(false)
+    }
+    
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::read_link(path)
+    }
+    
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect()
+    }
+    
+    #[cfg(unix)]
+    fn mode(&self, path: &Path) -> Option<u32> {
+        use std::os::unix::fs::PermissionsExt;
+        fs::metadata(path).ok().map(|metadata| metadata.permissions().mode() & 0o7777)
+    }
+    
+    #[cfg(not(unix))]
+    fn mode(&self, _path: &Path) -> Option<u32> {
+        None
+    }
+}
+
+/// In-memory filesystem (scripts from a database, object store or test fixtures)
+///
+/// Relative paths are relative to `/`, directories exist implicitly for
+/// every added file, and `..` is resolved lexically.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFileSystem {
+    /// File contents by absolute path
+    files: BTreeMap<PathBuf, Vec<u8>>,
+    /// Symbolic link targets by absolute path
+    symlinks: BTreeMap<PathBuf, PathBuf>,
+    /// Permission bits by absolute path
+    modes: BTreeMap<PathBuf, u32>,
+}
+
+impl MemoryFileSystem {
+    /// Create empty filesystem
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Add file (replacing existing content)
+    pub fn add_file(&mut self, path: impl AsRef<Path>, content: impl Into<Vec<u8>>) -> &mut Self {
+        self.files.insert(normalize(path.as_ref()), content.into());
+        self
+    }
+    
+    /// Add symbolic link (relative targets are relative to the link's directory)
+    pub fn add_symlink(&mut self, path: impl AsRef<Path>, target: impl AsRef<Path>) -> &mut Self {
+        self.symlinks.insert(normalize(path.as_ref()), target.as_ref().to_path_buf());
+        self
+    }
+    
+    /// Set permission bits of file (unset files have unknown permissions)
+    pub fn set_mode(&mut self, path: impl AsRef<Path>, mode: u32) -> &mut Self {
+        self.modes.insert(normalize(path.as_ref()), mode);
+        self
+    }
+    
+    /// Check if path is the root, a file, a link or a directory of one
+    fn exists(&self, path: &Path) -> bool {
+        path.parent().is_none()
+            || self.files.keys().chain(self.symlinks.keys()).any(|entry| entry.starts_with(path))
+    }
+    
+    /// Split path at its first symbolic link component (`link`, `rest`)
+    fn split_at_symlink(&self, path: &Path) -> Option<(PathBuf, PathBuf)> {
+        let mut prefix = PathBuf::new();
+        let mut components = path.components();
+        
+        while let Some(component) = components.next() {
+            prefix.push(component);
+            if self.symlinks.contains_key(&prefix) {
+                return Some((prefix, components.as_path().to_path_buf()));
+            }
+        }
+        
+        None
+    }
+    
+    /// Path with parent directory canonicalized and last component kept as is
+    fn resolve_parent(&self, path: &Path) -> Option<PathBuf> {
+        let path = normalize(path);
+        let file_name = path.file_name()?;
+        let parent = self.canonicalize(path.parent()?).ok()?;
+        Some(parent.join(file_name))
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let path = self.canonicalize(path)?;
+        self.files.get(&path)
+            .cloned()
+            .ok_or_else(|| not_found(&path))
+    }
+    
+    fn is_file(&self, path: &Path) -> bool {
+        self.canonicalize(path).is_ok_and(|path| self.files.contains_key(&path))
+    }
+    
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        // Same limit as Linux ELOOP
+        const MAX_HOPS: usize = 40;
+        
+        let mut current = normalize(path);
+        
+        for _ in 0..MAX_HOPS {
+            let Some((link, rest)) = self.split_at_symlink(&current) else {
+                return if self.exists(&current) { Ok(current) } else { Err(not_found(path)) };
+            };
+            let link_dir = link.parent().unwrap_or_else(|| Path::new("/"));
+            current = normalize(&link_dir.join(&self.symlinks[&link]).join(rest));
+        }
+        
+        Err(io::Error::other(format!("Too many levels of symbolic links: {}", path.display())))
+    }
+    
+    fn is_symlink(&self, path: &Path) -> bool {
+        self.resolve_parent(path).is_some_and(|path| self.symlinks.contains_key(&path))
+    }
+    
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        self.resolve_parent(path)
+            .and_then(|path| self.symlinks.get(&path).cloned())
+            .ok_or_else(|| io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Not a symbolic link: {}", path.display()),
+            ))
+    }
+    
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let dir = self.canonicalize(path)?;
+        if self.files.contains_key(&dir) {
+            return Err(io::Error::other(format!("Not a directory: {}", path.display())));
+        }
+        
+        // Direct children of files and links below the directory
+        let entries: BTreeSet<PathBuf> = self.files.keys()
+            .chain(self.symlinks.keys())
+            .filter_map(|entry| entry.strip_prefix(&dir).ok()?.components().next())
+            .map(|name| dir.join(name))
+            .collect();
+        
+        Ok(entries.into_iter().collect())
+    }
+    
+    fn mode(&self, path: &Path) -> Option<u32> {
+        let path = self.canonicalize(path).ok()?;
+        self.modes.get(&path).copied()
+    }
+}
+
+/// Absolute path with `.` and `..` removed lexically
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::from("/");
+    
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => normalized.push(component),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::Normal(name) => normalized.push(name),
+        }
+    }
+    
+    normalized
+}
+
+/// Not found error for path
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("No such file or directory: {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn sample_file_system() -> MemoryFileSystem {
+        let mut file_system = MemoryFileSystem::new();
+        file_system
+            .add_file("/project/main.sh", "#include lib/log.sh\n")
+            .add_file("/project/lib/log.sh", "echo log\n")
+            .add_symlink("/project/vendor", "lib")
+            .add_symlink("/project/lib/current.sh", "/project/lib/log.sh");
+        file_system
+    }
+    
+    #[test]
+    fn test_memory_read() {
+        let file_system = sample_file_system();
+        
+        assert_eq!(file_system.read(Path::new("/project/lib/log.sh")).unwrap(), b"echo log\n");
+        assert_eq!(file_system.read(Path::new("project/./lib/../main.sh")).unwrap(), b"#include lib/log.sh\n");
+        
+        let err = file_system.read(Path::new("/project/missing.sh")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(file_system.read(Path::new("/project/lib")).is_err());
+    }
+    
+    #[test]
+    fn test_memory_symlinks() {
+        let file_system = sample_file_system();
+        
+        assert_eq!(
+            file_system.canonicalize(Path::new("/project/vendor/current.sh")).unwrap(),
+            PathBuf::from("/project/lib/log.sh")
+        );
+        assert!(file_system.is_file(Path::new("/project/vendor/log.sh")));
+        assert!(!file_system.is_file(Path::new("/project/vendor")));
+        
+        assert!(file_system.is_symlink(Path::new("/project/vendor")));
+        assert!(file_system.is_symlink(Path::new("/project/vendor/current.sh")));
+        assert!(!file_system.is_symlink(Path::new("/project/vendor/log.sh")));
+        assert_eq!(file_system.read_link(Path::new("/project/vendor")).unwrap(), PathBuf::from("lib"));
+        
+        // Modes follow links to the file
+        let mut file_system = file_system;
+        file_system.set_mode("/project/lib/log.sh", 0o755);
+        assert_eq!(file_system.mode(Path::new("/project/vendor/current.sh")), Some(0o755));
+        assert_eq!(file_system.mode(Path::new("/project/main.sh")), None);
+        
+        // Link loops are reported instead of hanging
+        let mut file_system = MemoryFileSystem::new();
+        file_system.add_symlink("/a", "b").add_symlink("/b", "a");
+        assert!(file_system.canonicalize(Path::new("/a")).is_err());
+    }
+    
+    #[test]
+    fn test_memory_read_dir() {
+        let file_system = sample_file_system();
+        
+        assert_eq!(file_system.read_dir(Path::new("/project")).unwrap(), vec![
+            PathBuf::from("/project/lib"),
+            PathBuf::from("/project/main.sh"),
+            PathBuf::from("/project/vendor"),
+        ]);
+        assert_eq!(file_system.read_dir(Path::new("/project/vendor")).unwrap(), vec![
+            PathBuf::from("/project/lib/current.sh"),
+            PathBuf::from("/project/lib/log.sh"),
+        ]);
+        assert!(file_system.read_dir(Path::new("/project/main.sh")).is_err());
+        assert!(file_system.read_dir(Path::new("/missing")).is_err());
+    }
+}
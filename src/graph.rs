@@ -0,0 +1,217 @@
+//! Include graph of a processed script (`shrup deps`)
+
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+use serde::Serialize;
+
+use crate::parser::IncludeQuoteType;
+
+/// Output format of an include graph
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GraphFormat {
+    /// One file per line, in processing order
+    #[default]
+    List,
+    /// Indented include tree
+    Tree,
+    /// Files and annotated edges as JSON
+    Json,
+    /// Graphviz DOT
+    Dot,
+}
+
+impl FromStr for GraphFormat {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "list" => Ok(Self::List),
+            "tree" => Ok(Self::Tree),
+            "json" => Ok(Self::Json),
+            "dot" => Ok(Self::Dot),
+            _ => Err(format!("invalid graph format '{}' (expected list, tree, json or dot)", s)),
+        }
+    }
+}
+
+/// Include of one file by another
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IncludeEdge {
+    /// Including file (display path)
+    pub from: String,
+    /// Included file (display path)
+    pub to: String,
+    /// Line of the include directive in the including file
+    pub line: usize,
+    /// Quote type of the include directive
+    #[serde(rename = "quote")]
+    pub quote_type: IncludeQuoteType,
+}
+
+/// Files pulled into a script and the includes between them
+///
+/// Only includes in active conditional blocks are part of the graph.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IncludeGraph {
+    /// Files in processing order (root script first)
+    pub files: Vec<String>,
+    /// Includes in processing order
+    pub edges: Vec<IncludeEdge>,
+}
+
+impl IncludeGraph {
+    /// Create include graph
+    pub fn new(files: Vec<String>, edges: Vec<IncludeEdge>) -> Self {
+        Self { files, edges }
+    }
+    
+    /// Render in format
+    pub fn render(&self, format: GraphFormat) -> String {
+        match format {
+            GraphFormat::List => self.to_list(),
+            GraphFormat::Tree => self.to_tree(),
+            GraphFormat::Json => self.to_json() + "\n",
+            GraphFormat::Dot => self.to_dot(),
+        }
+    }
+    
+    /// One file per line
+    pub fn to_list(&self) -> String {
+        self.files.iter().map(|file| format!("{}\n", file)).collect()
+    }
+    
+    /// Include tree from the root script
+    ///
+    /// Files whose includes were already shown are marked with `(*)`.
+    pub fn to_tree(&self) -> String {
+        let Some(root) = self.files.first() else {
+            return String::new();
+        };
+        
+        let mut tree = format!("{}\n", root);
+        let mut expanded = HashSet::from([root.as_str()]);
+        self.write_subtree(root, "", &mut expanded, &mut tree);
+        tree
+    }
+    
+    /// Write included files of `file` below it
+    fn write_subtree<'a>(&'a self, file: &str, prefix: &str, expanded: &mut HashSet<&'a str>, tree: &mut String) {
+        let children: Vec<&IncludeEdge> = self.edges.iter().filter(|edge| edge.from == file).collect();
+        
+        for (index, edge) in children.iter().enumerate() {
+            let last = index + 1 == children.len();
+            let has_children = self.edges.iter().any(|child| child.from == edge.to);
+            let repeated = has_children && !expanded.insert(edge.to.as_str());
+            
+            tree.push_str(prefix);
+            tree.push_str(if last { "└── " } else { "├── " });
+            tree.push_str(&edge.to);
+            tree.push_str(if repeated { " (*)\n" } else { "\n" });
+            
+            if has_children && !repeated {
+                let prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+                self.write_subtree(&edge.to, &prefix, expanded, tree);
+            }
+        }
+    }
+    
+    /// Serialize to JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("include graph serialization cannot fail")
+    }
+    
+    /// Graphviz DOT with edges labeled by directive line
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph includes {\n");
+        
+        for file in &self.files {
+            dot.push_str(&format!("    {};\n", DotId(file)));
+        }
+        for edge in &self.edges {
+            dot.push_str(&format!("    {} -> {} [label=\"line {}\"];\n", DotId(&edge.from), DotId(&edge.to), edge.line));
+        }
+        
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// Quoted DOT identifier
+struct DotId<'a>(&'a str);
+
+impl fmt::Display for DotId<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\"{}\"", self.0.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn edge(from: &str, to: &str, line: usize, quote_type: IncludeQuoteType) -> IncludeEdge {
+        IncludeEdge { from: from.to_string(), to: to.to_string(), line, quote_type }
+    }
+    
+    fn sample_graph() -> IncludeGraph {
+        IncludeGraph::new(
+            vec!["main.sh".to_string(), "lib/net.sh".to_string(), "lib/log.sh".to_string(), "lib/\"x\".sh".to_string()],
+            vec![
+                edge("main.sh", "lib/net.sh", 2, IncludeQuoteType::None),
+                edge("lib/net.sh", "lib/log.sh", 1, IncludeQuoteType::DoubleQuotes),
+                edge("main.sh", "lib/log.sh", 3, IncludeQuoteType::AngleBrackets),
+                edge("lib/log.sh", "lib/\"x\".sh", 4, IncludeQuoteType::SingleQuotes),
+            ],
+        )
+    }
+    
+    #[test]
+    fn test_graph_formats() {
+        assert_eq!("dot".parse::<GraphFormat>().unwrap(), GraphFormat::Dot);
+        assert_eq!(
+            "svg".parse::<GraphFormat>().unwrap_err(),
+            "invalid graph format 'svg' (expected list, tree, json or dot)"
+        );
+    }
+    
+    #[test]
+    fn test_list_and_tree() {
+        let graph = sample_graph();
+        
+        assert_eq!(graph.render(GraphFormat::List), "main.sh\nlib/net.sh\nlib/log.sh\nlib/\"x\".sh\n");
+        assert_eq!(graph.render(GraphFormat::Tree), concat!(
+            "main.sh\n",
+            "├── lib/net.sh\n",
+            "│   └── lib/log.sh\n",
+            "│       └── lib/\"x\".sh\n",
+            "└── lib/log.sh (*)\n",
+        ));
+        
+        // Cycles (possible with `#pragma once`) terminate
+        let graph = IncludeGraph::new(
+            vec!["a.sh".to_string(), "b.sh".to_string()],
+            vec![edge("a.sh", "b.sh", 1, IncludeQuoteType::None), edge("b.sh", "a.sh", 1, IncludeQuoteType::None)],
+        );
+        assert_eq!(graph.to_tree(), "a.sh\n└── b.sh\n    └── a.sh (*)\n");
+    }
+    
+    #[test]
+    fn test_json_and_dot() {
+        let graph = sample_graph();
+        
+        let json: serde_json::Value = serde_json::from_str(&graph.render(GraphFormat::Json)).unwrap();
+        assert_eq!(json["files"][0], "main.sh");
+        assert_eq!(
+            json["edges"][1],
+            serde_json::json!({"from": "lib/net.sh", "to": "lib/log.sh", "line": 1, "quote": "double_quotes"})
+        );
+        assert_eq!(json["edges"][2]["quote"], "angle_brackets");
+        
+        let dot = graph.render(GraphFormat::Dot);
+        assert!(dot.starts_with("digraph includes {\n    \"main.sh\";\n"));
+        assert!(dot.contains("    \"main.sh\" -> \"lib/net.sh\" [label=\"line 2\"];\n"));
+        assert!(dot.contains("    \"lib/log.sh\" -> \"lib/\\\"x\\\".sh\" [label=\"line 4\"];\n"));
+        assert!(dot.ends_with("}\n"));
+    }
+}
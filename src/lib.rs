@@ -1,11 +1,27 @@
 //! Shell script preprocessor library
 
+pub mod depfile;
 pub mod error;
+pub mod expr;
+pub mod filesystem;
+pub mod graph;
+pub mod macros;
 pub mod parser;
 pub mod preprocessor;
 pub mod resolver;
+pub mod runtime;
+pub mod sourcemap;
+pub mod trace;
 
+pub use depfile::*;
 pub use error::*;
+pub use expr::*;
+pub use filesystem::*;
+pub use graph::*;
+pub use macros::*;
 pub use parser::*;
 pub use preprocessor::*;
-pub use resolver::*;
\ No newline at end of file
+pub use resolver::*;
+pub use runtime::*;
+pub use sourcemap::*;
+pub use trace::*;
\ No newline at end of file
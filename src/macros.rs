@@ -0,0 +1,344 @@
+//! Macro table and expansion (`#define` / `-D`)
+
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Macro definition
+#[derive(Debug, Clone, PartialEq)]
+pub struct Macro {
+    /// Macro name
+    pub name: String,
+    /// Parameter names (function-like macros only)
+    pub params: Option<Vec<String>>,
+    /// Replacement text
+    pub body: String,
+}
+
+/// Defined macros
+///
+/// Macros are referenced in script lines as `@NAME@`, function-like macros
+/// as `@NAME(arg, ...)@` with parameters referenced as `@param@` in the
+/// body. References to undefined names are left untouched, so unrelated
+/// `@` characters in scripts are not affected.
+#[derive(Debug, Clone, Default)]
+pub struct MacroTable {
+    macros: HashMap<String, Macro>,
+}
+
+impl MacroTable {
+    /// Create empty table
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Create table with predefined macros (`__DATE__`, `__SHRUP_VERSION__`)
+    pub fn with_predefined() -> Self {
+        let mut table = Self::new();
+        table.define("__DATE__".to_string(), build_date());
+        table.define("__SHRUP_VERSION__".to_string(), env!("CARGO_PKG_VERSION").to_string());
+        table
+    }
+    
+    /// Update location macros (`__FILE__`, `__LINE__`) for line being processed
+    pub fn set_location(&mut self, file: &str, line_number: usize) {
+        if self.get("__FILE__").is_none_or(|current| current.body != file) {
+            self.define("__FILE__".to_string(), file.to_string());
+        }
+        self.define("__LINE__".to_string(), line_number.to_string());
+    }
+    
+    /// Define (or redefine) object-like macro
+    pub fn define(&mut self, name: String, body: String) {
+        self.macros.insert(name.clone(), Macro { name, params: None, body });
+    }
+    
+    /// Define (or redefine) function-like macro
+    pub fn define_function(&mut self, name: String, params: Vec<String>, body: String) {
+        self.macros.insert(name.clone(), Macro { name, params: Some(params), body });
+    }
+    
+    /// Remove macro definition
+    pub fn undefine(&mut self, name: &str) {
+        self.macros.remove(name);
+    }
+    
+    /// Get macro by name
+    pub fn get(&self, name: &str) -> Option<&Macro> {
+        self.macros.get(name)
+    }
+    
+    /// Check if macro is defined
+    pub fn is_defined(&self, name: &str) -> bool {
+        self.macros.contains_key(name)
+    }
+    
+    /// Expand `@NAME@` and `@NAME(args)@` references in text
+    pub fn expand(&self, text: &str) -> Result<String, String> {
+        self.expand_with(text, &mut Vec::new())
+    }
+    
+    /// Expand text, leaving references to macros already being expanded as-is
+    fn expand_with<'a>(&'a self, text: &str, expanding: &mut Vec<&'a str>) -> Result<String, String> {
+        let mut result = String::with_capacity(text.len());
+        let mut rest = text;
+        
+        while let Some(start) = rest.find('@') {
+            result.push_str(&rest[..start]);
+            let after = &rest[start + 1..];
+            
+            let name_end = after.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(after.len());
+            let found = self.macros.get_key_value(&after[..name_end])
+                .filter(|(name, _)| !expanding.contains(&name.as_str()));
+            
+            let expansion = match found {
+                Some((name, definition)) => match &definition.params {
+                    None if after[name_end..].starts_with('@') => {
+                        Some((name, definition.body.clone(), name_end + 1))
+                    }
+                    Some(params) if after[name_end..].starts_with('(') => {
+                        match split_arguments(&after[name_end + 1..]) {
+                            Some((args, consumed)) if after[name_end + 1 + consumed..].starts_with('@') => {
+                                let body = self.substitute_params(definition, params, args, expanding)?;
+                                Some((name, body, name_end + 1 + consumed + 1))
+                            }
+                            _ => None,
+                        }
+                    }
+                    _ => None,
+                },
+                None => None,
+            };
+            
+            match expansion {
+                Some((name, body, consumed)) => {
+                    // Replacement text may reference other macros
+                    expanding.push(name);
+                    let expanded = self.expand_with(&body, expanding);
+                    expanding.pop();
+                    result.push_str(&expanded?);
+                    rest = &after[consumed..];
+                }
+                None => {
+                    // Not a reference; closing `@` may start the next one
+                    result.push('@');
+                    rest = after;
+                }
+            }
+        }
+        
+        result.push_str(rest);
+        Ok(result)
+    }
+    
+    /// Replace `@param@` in function-like macro body with expanded arguments
+    fn substitute_params<'a>(
+        &'a self,
+        definition: &Macro,
+        params: &[String],
+        mut args: Vec<&str>,
+        expanding: &mut Vec<&'a str>,
+    ) -> Result<String, String> {
+        // `@NAME()@` passes no arguments
+        if params.is_empty() && args.len() == 1 && args[0].is_empty() {
+            args.clear();
+        }
+        
+        if args.len() != params.len() {
+            return Err(format!(
+                "macro '{}' expects {} argument(s), got {}",
+                definition.name, params.len(), args.len()
+            ));
+        }
+        
+        let args = args.into_iter()
+            .map(|arg| self.expand_with(arg, expanding))
+            .collect::<Result<Vec<_>, _>>()?;
+        
+        let mut result = String::with_capacity(definition.body.len());
+        let mut rest = definition.body.as_str();
+        
+        while let Some(start) = rest.find('@') {
+            result.push_str(&rest[..start]);
+            let after = &rest[start + 1..];
+            
+            let param = after.find('@')
+                .and_then(|end| params.iter().position(|param| *param == after[..end]));
+            
+            match param {
+                Some(index) => {
+                    result.push_str(&args[index]);
+                    rest = &after[params[index].len() + 1..];
+                }
+                None => {
+                    result.push('@');
+                    rest = after;
+                }
+            }
+        }
+        
+        result.push_str(rest);
+        Ok(result)
+    }
+}
+
+/// Build date as `YYYY-MM-DD` (honors `SOURCE_DATE_EPOCH` for reproducible builds)
+fn build_date() -> String {
+    let seconds = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs())
+        });
+    
+    format_date(seconds)
+}
+
+/// Format Unix timestamp as UTC `YYYY-MM-DD`
+fn format_date(seconds: u64) -> String {
+    // Days to civil date (Howard Hinnant's algorithm)
+    let days = (seconds / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Split macro arguments up to the closing `)`, respecting shell quoting
+///
+/// Commas inside quotes, nested parentheses (`$(cmd a, b)`) or after a
+/// backslash do not separate arguments. Returns trimmed arguments and the
+/// number of bytes consumed including the closing `)`.
+fn split_arguments(input: &str) -> Option<(Vec<&str>, usize)> {
+    let mut args = Vec::new();
+    let mut arg_start = 0;
+    let mut depth = 0;
+    let mut quote = None;
+    let mut chars = input.char_indices();
+    
+    while let Some((index, c)) = chars.next() {
+        match (quote, c) {
+            // No escapes inside single quotes
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => {
+                chars.next();
+            }
+            (Some('"'), '"') => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') if depth > 0 => depth -= 1,
+            (None, ')') => {
+                args.push(input[arg_start..index].trim());
+                return Some((args, index + 1));
+            }
+            (None, ',') if depth == 0 => {
+                args.push(input[arg_start..index].trim());
+                arg_start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    
+    // Unterminated argument list
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn table(definitions: &[(&str, &str)]) -> MacroTable {
+        let mut table = MacroTable::new();
+        for (name, body) in definitions {
+            table.define(name.to_string(), body.to_string());
+        }
+        table
+    }
+    
+    fn expand(table: &MacroTable, text: &str) -> String {
+        table.expand(text).unwrap()
+    }
+    
+    #[test]
+    fn test_expand_object_macros() {
+        let table = table(&[("VERSION", "1.2.3"), ("PREFIX", "/usr/local")]);
+        assert_eq!(expand(&table, "VERSION=\"@VERSION@\""), "VERSION=\"1.2.3\"");
+        assert_eq!(expand(&table, "cp x @PREFIX@/bin/@PREFIX@"), "cp x /usr/local/bin//usr/local");
+        assert_eq!(expand(&table, "user@example.com @UNDEFINED@"), "user@example.com @UNDEFINED@");
+        assert_eq!(expand(&table, "a@b@VERSION@"), "a@b1.2.3");
+    }
+    
+    #[test]
+    fn test_expand_nested_macros() {
+        let table = table(&[("PREFIX", "/usr"), ("BINDIR", "@PREFIX@/bin"), ("SELF", "<@SELF@>")]);
+        assert_eq!(expand(&table, "@BINDIR@"), "/usr/bin");
+        // Self references are not expanded again
+        assert_eq!(expand(&table, "@SELF@"), "<@SELF@>");
+    }
+    
+    #[test]
+    fn test_expand_function_macros() {
+        let mut table = table(&[("APP", "shrup")]);
+        table.define_function(
+            "LOG".to_string(),
+            vec!["level".to_string(), "msg".to_string()],
+            "echo \"[@APP@:@level@] @msg@\" >&2".to_string(),
+        );
+        table.define_function("NOW".to_string(), Vec::new(), "$(date +%s)".to_string());
+        
+        assert_eq!(
+            expand(&table, "@LOG(info, starting up)@"),
+            "echo \"[shrup:info] starting up\" >&2"
+        );
+        // Commas inside quotes and command substitutions stay in the argument
+        assert_eq!(
+            expand(&table, "@LOG(warn, \"a, b\")@; @LOG('x,y', $(cut -d, -f1 f))@"),
+            "echo \"[shrup:warn] \"a, b\"\" >&2; echo \"[shrup:'x,y'] $(cut -d, -f1 f)\" >&2"
+        );
+        // Arguments are expanded
+        assert_eq!(expand(&table, "@LOG(@APP@, t=@NOW()@)@"), "echo \"[shrup:shrup] t=$(date +%s)\" >&2");
+        // Not invocations
+        assert_eq!(expand(&table, "@LOG@ @LOG(a, b) @NOW(@"), "@LOG@ @LOG(a, b) @NOW(@");
+    }
+    
+    #[test]
+    fn test_expand_function_macro_errors() {
+        let mut table = MacroTable::new();
+        table.define_function("LOG".to_string(), vec!["level".to_string(), "msg".to_string()], String::new());
+        assert!(table.expand("@LOG(info)@").is_err());
+        assert!(table.expand("@LOG(a, b, c)@").is_err());
+    }
+    
+    #[test]
+    fn test_split_arguments() {
+        assert_eq!(split_arguments("a, \"b,c\" ,d) rest"), Some((vec!["a", "\"b,c\"", "d"], 12)));
+        assert_eq!(split_arguments("\\,x, (y, z))"), Some((vec!["\\,x", "(y, z)"], 12)));
+        assert_eq!(split_arguments("'it\\'s', y)"), None);
+        assert_eq!(split_arguments("a, b"), None);
+    }
+    
+    #[test]
+    fn test_location_macros() {
+        let mut table = MacroTable::with_predefined();
+        table.set_location("lib/net.sh", 42);
+        assert_eq!(expand(&table, "echo \"@__FILE__@:@__LINE__@\""), "echo \"lib/net.sh:42\"");
+        assert_eq!(expand(&table, "@__SHRUP_VERSION__@"), env!("CARGO_PKG_VERSION"));
+        assert_eq!(table.get("__DATE__").map(|date| date.body.len()), Some(10));
+    }
+    
+    #[test]
+    fn test_format_date() {
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(951_782_400), "2000-02-29");
+        assert_eq!(format_date(1_791_936_000), "2026-10-14");
+    }
+}
@@ -1,10 +1,15 @@
 //! Shell script preprocessor
 
-use std::path::PathBuf;
-use anyhow::Result;
-use clap::Parser;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
 
-use shrup::PreprocessorBuilder;
+use shrup::{
+    Depfile, DuplicateIncludePolicy, FileResolver, GraphFormat, IncludeParser, InputEncoding, LineEndings, PreprocessorBuilder, ShebangPolicy,
+    SourceMap, SymlinkPolicy, TraceRemapper, STDIO_PATH,
+};
 
 /// Shell script preprocessor
 #[derive(Parser)]
@@ -12,55 +17,313 @@ use shrup::PreprocessorBuilder;
 #[command(version = "0.1.0")]
 #[command(about = "A shell script preprocessor")]
 #[command(long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    /// Input file to process
-    #[arg(value_name = "INPUT")]
-    input: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+    
+    /// Input file to process (`-` for stdin; `./trace` or `./deps` for files named like a subcommand)
+    #[arg(value_name = "INPUT", required = true)]
+    input: Option<PathBuf>,
     
-    /// Output file path
+    /// Output file path (`-` or omitted for stdout)
     #[arg(value_name = "OUTPUT")]
-    output: PathBuf,
+    output: Option<PathBuf>,
     
     /// Add debug comments to output
     #[arg(short, long)]
     debug: bool,
     
+    /// Output line endings: preserve, lf, crlf (default: preserve)
+    #[arg(long, value_name = "MODE", default_value = "preserve")]
+    line_endings: LineEndings,
+    
+    /// Write source map mapping output lines to source file:line (OUTPUT.map)
+    #[arg(long)]
+    source_map: bool,
+    
+    /// Embed line-origin table so the script can report source file:line (`__shrup_origin "$LINENO"`)
+    #[arg(long)]
+    runtime_origins: bool,
+    
+    /// Write Make/Ninja dependency file listing OUTPUT's included files
+    #[arg(long, value_name = "PATH", conflicts_with = "make_depfile")]
+    depfile: Option<PathBuf>,
+    
+    /// `-MD`: write dependency file to OUTPUT.d, like `gcc -MD`
+    #[arg(short = 'M', value_name = "D", value_parser = ["D"])]
+    make_depfile: Option<String>,
+    
+    /// Don't rewrite output (keeping its mtime) when the content is unchanged
+    #[arg(long)]
+    skip_unchanged: bool,
+    
+    /// Permission bits of output file in octal, e.g. 755 (default: input file's)
+    #[arg(long, value_name = "MODE", value_parser = parse_mode)]
+    mode: Option<u32>,
+    
+    #[command(flatten)]
+    resolve: ResolveArgs,
+}
+
+/// Options controlling how includes are resolved (shared with `shrup deps`)
+#[derive(clap::Args)]
+struct ResolveArgs {
+    /// Base directory for absolute includes and stdin (default: input file's directory, or `.` for stdin)
+    #[arg(long, value_name = "DIR")]
+    base_dir: Option<PathBuf>,
+    
     /// Max include depth (default: 100)
     #[arg(long, default_value = "100")]
     max_depth: usize,
+    
+    /// Add directory to include search path, searched before SHRUP_PATH (repeatable)
+    #[arg(short = 'I', value_name = "DIR")]
+    include_paths: Vec<PathBuf>,
+    
+    /// Directory includes must stay within (default: input file's directory)
+    #[arg(long, value_name = "DIR")]
+    root: Option<PathBuf>,
+    
+    /// Allow includes outside the root directory (trusted builds only)
+    #[arg(long)]
+    allow_outside_root: bool,
+    
+    /// Symbolic links in include paths: follow, refuse, refuse-escaping (default: follow)
+    #[arg(long, value_name = "POLICY", default_value = "follow")]
+    symlinks: SymlinkPolicy,
+    
+    /// Files included more than once: always, once, error (default: always)
+    #[arg(long, value_name = "POLICY", default_value = "always")]
+    duplicates: DuplicateIncludePolicy,
+    
+    /// Shebangs of included files: drop, keep, error (default: drop)
+    #[arg(long, value_name = "POLICY", default_value = "drop")]
+    shebangs: ShebangPolicy,
+    
+    /// Encoding of source files without BOM or `coding:` comment, e.g. shift_jis, latin1 (default: auto = UTF-8)
+    #[arg(long, value_name = "ENCODING", default_value = "auto")]
+    input_encoding: InputEncoding,
+    
+    /// Define macro, referenced as @NAME@ or in #if (repeatable)
+    #[arg(short = 'D', value_name = "NAME[=VALUE]", value_parser = parse_define)]
+    defines: Vec<(String, String)>,
+}
+
+/// Subcommands (without one, INPUT is preprocessed)
+#[derive(Subcommand)]
+enum Command {
+    /// Rewrite bundle line references in bash errors and `set -x` traces to source file:line
+    Trace(TraceArgs),
+    /// Print the files a script includes as a list, tree, JSON or Graphviz DOT, without writing output
+    Deps(DepsArgs),
+}
+
+/// Arguments of `shrup deps`
+#[derive(clap::Args)]
+struct DepsArgs {
+    /// Script to resolve (`-` for stdin)
+    #[arg(value_name = "INPUT")]
+    input: PathBuf,
+    
+    /// Output format: list, tree, json, dot (default: list)
+    #[arg(long, value_name = "FORMAT", default_value = "list")]
+    format: GraphFormat,
+    
+    #[command(flatten)]
+    resolve: ResolveArgs,
+}
+
+/// Arguments of `shrup trace`
+#[derive(clap::Args)]
+struct TraceArgs {
+    /// Source map, or bundled script with a `.map` sidecar
+    #[arg(value_name = "MAP")]
+    map: PathBuf,
+    
+    /// Log to remap (`-` or omitted for stdin)
+    #[arg(value_name = "LOG")]
+    log: Option<PathBuf>,
+    
+    /// Script name in the log (default: bundle file name; `bash` for piped scripts)
+    #[arg(long, value_name = "NAME")]
+    script: Option<String>,
+    
+    /// Also rewrite bare line numbers of `PS4='+${LINENO}: '` traces (`++12: foo`)
+    #[arg(long)]
+    lineno_prefix: bool,
+}
+
+/// Parse octal `--mode` (`755`, `0755`)
+fn parse_mode(arg: &str) -> Result<u32, String> {
+    u32::from_str_radix(arg, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+        .ok_or_else(|| format!("invalid mode '{}' (expected octal permission bits such as 755)", arg))
+}
+
+/// Parse `-D NAME[=VALUE]` (value defaults to 1)
+fn parse_define(arg: &str) -> Result<(String, String), String> {
+    let (name, value) = arg.split_once('=').unwrap_or((arg, "1"));
+    
+    if !IncludeParser::is_symbol_name(name) {
+        return Err(format!("invalid symbol name '{}' (expected upper case such as DEBUG)", name));
+    }
+    
+    Ok((name.to_string(), value.to_string()))
+}
+
+/// Validate input and create builder with include resolution options
+fn preprocessor_builder(input: &Path, args: ResolveArgs) -> Result<PreprocessorBuilder> {
+    let is_stdin = input == Path::new(STDIO_PATH);
+    
+    if !is_stdin && !input.exists() {
+        anyhow::bail!("Input file does not exist: {}", input.display());
+    }
+    
+    if !is_stdin && !input.is_file() {
+        anyhow::bail!("Input path is not a file: {}", input.display());
+    }
+    
+    // Get base directory (parent of `main.sh` is empty, stdin has none)
+    let base_directory = args.base_dir.unwrap_or_else(|| {
+        input
+            .parent()
+            .filter(|parent| !is_stdin && !parent.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."))
+            .to_path_buf()
+    });
+    
+    let mut builder = PreprocessorBuilder::new()
+        .max_include_depth(args.max_depth)
+        .base_directory(base_directory)
+        .library_paths(FileResolver::library_paths_from_env())
+        .allow_outside_root(args.allow_outside_root)
+        .symlinks(args.symlinks)
+        .duplicate_includes(args.duplicates)
+        .shebangs(args.shebangs)
+        .input_encoding(args.input_encoding);
+    
+    if let Some(root) = args.root {
+        builder = builder.root_directory(root);
+    }
+    
+    for include_path in args.include_paths {
+        builder = builder.include_path(include_path);
+    }
+    
+    for (name, value) in args.defines {
+        builder = builder.define(name, value);
+    }
+    
+    Ok(builder)
+}
+
+/// Print include graph of script
+fn run_deps(args: DepsArgs) -> Result<()> {
+    let preprocessor = preprocessor_builder(&args.input, args.resolve)?.build();
+    let graph = preprocessor.include_graph(&args.input)?;
+    
+    io::stdout().lock().write_all(graph.render(args.format).as_bytes())
+        .context("Failed to write to stdout")?;
+    
+    Ok(())
+}
+
+/// Remap line references in log through the bundle's source map
+fn run_trace(args: TraceArgs) -> Result<()> {
+    // Accept the bundled script in place of its map
+    let map_path = if args.map.extension().is_some_and(|extension| extension == "map") {
+        args.map.clone()
+    } else {
+        SourceMap::path_for(&args.map)
+    };
+    
+    let json = fs::read_to_string(&map_path)
+        .with_context(|| format!("Failed to read source map: {}", map_path.display()))?;
+    let source_map = SourceMap::from_json(&json)
+        .map_err(anyhow::Error::msg)
+        .with_context(|| format!("Failed to read source map: {}", map_path.display()))?;
+    
+    let script_name = args.script
+        .or_else(|| source_map.file.clone())
+        .or_else(|| map_path.file_stem().map(|stem| stem.to_string_lossy().into_owned()))
+        .unwrap_or_default();
+    let remapper = TraceRemapper::new(source_map, script_name)
+        .lineno_prefix(args.lineno_prefix);
+    
+    let mut log: Box<dyn BufRead> = match &args.log {
+        Some(path) if path != Path::new(STDIO_PATH) => Box::new(BufReader::new(
+            fs::File::open(path).with_context(|| format!("Failed to read log: {}", path.display()))?,
+        )),
+        _ => Box::new(io::stdin().lock()),
+    };
+    
+    // Stream line by line so `bash bundle.sh 2>&1 | shrup trace bundle.sh` shows output live
+    let mut stdout = io::stdout().lock();
+    let mut line = Vec::new();
+    while log.read_until(b'\n', &mut line).context("Failed to read log")? > 0 {
+        stdout.write_all(remapper.remap_line(&String::from_utf8_lossy(&line)).as_bytes())
+            .context("Failed to write to stdout")?;
+        stdout.flush().context("Failed to write to stdout")?;
+        line.clear();
+    }
+    
+    Ok(())
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
     
-    if !args.input.exists() {
-        anyhow::bail!("Input file does not exist: {}", args.input.display());
-    }
+    let input = match args.command {
+        Some(Command::Trace(trace_args)) => return run_trace(trace_args),
+        Some(Command::Deps(deps_args)) => return run_deps(deps_args),
+        None => args.input.clone().expect("INPUT is required without subcommand"),
+    };
+    let output = args.output.clone().unwrap_or_else(|| PathBuf::from(STDIO_PATH));
     
-    if !args.input.is_file() {
-        anyhow::bail!("Input path is not a file: {}", args.input.display());
+    if args.source_map && output == Path::new(STDIO_PATH) {
+        anyhow::bail!("--source-map requires an OUTPUT file");
     }
     
-    // Get base directory
-    let base_directory = args.input
-        .parent()
-        .unwrap_or_else(|| std::path::Path::new("."))
-        .to_path_buf();
+    let depfile = match args.make_depfile {
+        Some(_) => Some(Depfile::path_for(&output)),
+        None => args.depfile.clone(),
+    };
+    if depfile.is_some() && output == Path::new(STDIO_PATH) {
+        anyhow::bail!("--depfile and -MD require an OUTPUT file");
+    }
     
     // Create preprocessor
-    let preprocessor = PreprocessorBuilder::new()
+    let mut builder = preprocessor_builder(&input, args.resolve)?
         .debug_mode(args.debug)
-        .max_include_depth(args.max_depth)
-        .base_directory(base_directory)
-        .build();
+        .line_endings(args.line_endings)
+        .source_map(args.source_map)
+        .runtime_origins(args.runtime_origins)
+        .skip_unchanged_output(args.skip_unchanged);
+    
+    if let Some(depfile) = depfile {
+        builder = builder.depfile(depfile);
+    }
+    
+    #[cfg(not(unix))]
+    if args.mode.is_some() {
+        anyhow::bail!("--mode is only supported on Unix");
+    }
+    
+    if let Some(mode) = args.mode {
+        builder = builder.output_mode(mode);
+    }
+    
+    let preprocessor = builder.build();
     
     // Process file
-    match preprocessor.process_file(&args.input, &args.output) {
+    match preprocessor.process_file(&input, &output) {
         Ok(()) => {
             if args.debug {
                 eprintln!("✓ Successfully processed {} -> {}", 
-                         args.input.display(), 
-                         args.output.display());
+                         input.display(), 
+                         output.display());
             }
         }
         Err(e) => {
@@ -89,23 +352,210 @@ mod tests {
     #[test]
     fn test_args_parsing() {
         // Test basic argument parsing
-        let args = Args::try_parse_from(&["shrup", "input.sh", "output.sh"]).unwrap();
-        assert_eq!(args.input, PathBuf::from("input.sh"));
-        assert_eq!(args.output, PathBuf::from("output.sh"));
-        assert_eq!(args.debug, false);
-        assert_eq!(args.max_depth, 100);
+        let args = Args::try_parse_from(["shrup", "input.sh", "output.sh"]).unwrap();
+        assert_eq!(args.input, Some(PathBuf::from("input.sh")));
+        assert_eq!(args.output, Some(PathBuf::from("output.sh")));
+        assert!(!args.debug);
+        assert_eq!(args.resolve.max_depth, 100);
+    }
+    
+    #[test]
+    fn test_args_with_stdio() {
+        let args = Args::try_parse_from(["shrup", "main.sh"]).unwrap();
+        assert_eq!(args.output, None);
+        
+        let args = Args::try_parse_from(["shrup", "--base-dir", "src", "-", "-"]).unwrap();
+        assert_eq!(args.input, Some(PathBuf::from("-")));
+        assert_eq!(args.output, Some(PathBuf::from("-")));
+        assert_eq!(args.resolve.base_dir, Some(PathBuf::from("src")));
+    }
+    
+    #[test]
+    fn test_args_with_trace() {
+        let args = Args::try_parse_from(["shrup", "trace", "--script", "bash", "dist/deploy.sh", "ci.log"]).unwrap();
+        let Some(Command::Trace(trace_args)) = args.command else {
+            panic!("expected trace subcommand");
+        };
+        assert_eq!(trace_args.map, PathBuf::from("dist/deploy.sh"));
+        assert_eq!(trace_args.log, Some(PathBuf::from("ci.log")));
+        assert_eq!(trace_args.script.as_deref(), Some("bash"));
+        assert!(!trace_args.lineno_prefix);
+        
+        let args = Args::try_parse_from(["shrup", "trace", "--lineno-prefix", "dist/deploy.sh"]).unwrap();
+        let Some(Command::Trace(trace_args)) = args.command else {
+            panic!("expected trace subcommand");
+        };
+        assert!(trace_args.lineno_prefix);
+        
+        // Preprocessing still needs INPUT
+        assert!(Args::try_parse_from(["shrup"]).is_err());
+        assert!(Args::try_parse_from(["shrup", "input.sh"]).unwrap().command.is_none());
+    }
+    
+    #[test]
+    fn test_args_subcommand_precedence() {
+        // Subcommand names win over INPUT files with the same name
+        let args = Args::try_parse_from(["shrup", "trace", "out.sh"]).unwrap();
+        assert!(matches!(args.command, Some(Command::Trace(_))));
+        let args = Args::try_parse_from(["shrup", "deps", "out.sh"]).unwrap();
+        assert!(matches!(args.command, Some(Command::Deps(_))));
+        
+        // A path spelling selects the file
+        let args = Args::try_parse_from(["shrup", "./trace", "out.sh"]).unwrap();
+        assert!(args.command.is_none());
+        assert_eq!(args.input, Some(PathBuf::from("./trace")));
+        let args = Args::try_parse_from(["shrup", "./deps"]).unwrap();
+        assert!(args.command.is_none());
+        assert_eq!(args.input, Some(PathBuf::from("./deps")));
+    }
+    
+    #[test]
+    fn test_args_with_deps() {
+        let args = Args::try_parse_from(["shrup", "deps", "-I", "lib", "-DPROD", "--format", "dot", "main.sh"]).unwrap();
+        let Some(Command::Deps(deps_args)) = args.command else {
+            panic!("expected deps subcommand");
+        };
+        assert_eq!(deps_args.input, PathBuf::from("main.sh"));
+        assert_eq!(deps_args.format, GraphFormat::Dot);
+        assert_eq!(deps_args.resolve.include_paths, vec![PathBuf::from("lib")]);
+        assert_eq!(deps_args.resolve.defines, vec![("PROD".to_string(), "1".to_string())]);
+        
+        let args = Args::try_parse_from(["shrup", "deps", "main.sh"]).unwrap();
+        let Some(Command::Deps(deps_args)) = args.command else {
+            panic!("expected deps subcommand");
+        };
+        assert_eq!(deps_args.format, GraphFormat::List);
+        
+        assert!(Args::try_parse_from(["shrup", "deps", "--format", "svg", "main.sh"]).is_err());
+        assert!(Args::try_parse_from(["shrup", "deps", "--source-map", "main.sh"]).is_err());
     }
     
     #[test]
     fn test_args_with_debug() {
-        let args = Args::try_parse_from(&["shrup", "--debug", "input.sh", "output.sh"]).unwrap();
-        assert_eq!(args.debug, true);
+        let args = Args::try_parse_from(["shrup", "--debug", "input.sh", "output.sh"]).unwrap();
+        assert!(args.debug);
     }
     
     #[test]
     fn test_args_with_max_depth() {
-        let args = Args::try_parse_from(&["shrup", "--max-depth", "50", "input.sh", "output.sh"]).unwrap();
-        assert_eq!(args.max_depth, 50);
+        let args = Args::try_parse_from(["shrup", "--max-depth", "50", "input.sh", "output.sh"]).unwrap();
+        assert_eq!(args.resolve.max_depth, 50);
+    }
+    
+    #[test]
+    fn test_args_with_duplicates() {
+        let args = Args::try_parse_from(["shrup", "--duplicates", "once", "input.sh", "output.sh"]).unwrap();
+        assert_eq!(args.resolve.duplicates, DuplicateIncludePolicy::Once);
+        assert!(Args::try_parse_from(["shrup", "--duplicates", "never", "input.sh", "output.sh"]).is_err());
+    }
+    
+    #[test]
+    fn test_args_with_include_paths() {
+        let args = Args::try_parse_from(["shrup", "-I", "lib", "-Ivendor", "input.sh", "output.sh"]).unwrap();
+        assert_eq!(args.resolve.include_paths, vec![PathBuf::from("lib"), PathBuf::from("vendor")]);
+    }
+    
+    #[test]
+    fn test_args_with_root() {
+        let args = Args::try_parse_from(["shrup", "--root", "..", "input.sh", "output.sh"]).unwrap();
+        assert_eq!(args.resolve.root, Some(PathBuf::from("..")));
+        assert!(!args.resolve.allow_outside_root);
+        
+        let args = Args::try_parse_from(["shrup", "--allow-outside-root", "input.sh", "output.sh"]).unwrap();
+        assert!(args.resolve.allow_outside_root);
+    }
+    
+    #[test]
+    fn test_args_with_symlinks() {
+        let args = Args::try_parse_from(["shrup", "input.sh", "output.sh"]).unwrap();
+        assert_eq!(args.resolve.symlinks, SymlinkPolicy::Follow);
+        
+        let args = Args::try_parse_from(["shrup", "--symlinks", "refuse-escaping", "input.sh", "output.sh"]).unwrap();
+        assert_eq!(args.resolve.symlinks, SymlinkPolicy::RefuseEscaping);
+    }
+    
+    #[test]
+    fn test_args_with_shebangs() {
+        let args = Args::try_parse_from(["shrup", "input.sh", "output.sh"]).unwrap();
+        assert_eq!(args.resolve.shebangs, ShebangPolicy::Drop);
+        
+        let args = Args::try_parse_from(["shrup", "--shebangs", "error", "input.sh", "output.sh"]).unwrap();
+        assert_eq!(args.resolve.shebangs, ShebangPolicy::Error);
+    }
+    
+    #[test]
+    fn test_args_with_line_endings() {
+        let args = Args::try_parse_from(["shrup", "--line-endings", "crlf", "input.sh", "output.sh"]).unwrap();
+        assert_eq!(args.line_endings, LineEndings::Crlf);
+        assert!(Args::try_parse_from(["shrup", "--line-endings", "mac", "input.sh", "output.sh"]).is_err());
+    }
+    
+    #[test]
+    fn test_args_with_input_encoding() {
+        let args = Args::try_parse_from(["shrup", "input.sh", "output.sh"]).unwrap();
+        assert_eq!(args.resolve.input_encoding, InputEncoding::Auto);
+        
+        let args = Args::try_parse_from(["shrup", "--input-encoding", "sjis", "input.sh", "output.sh"]).unwrap();
+        assert_eq!(args.resolve.input_encoding, InputEncoding::Explicit(encoding_rs::SHIFT_JIS));
+        assert!(Args::try_parse_from(["shrup", "--input-encoding", "klingon", "input.sh", "output.sh"]).is_err());
+    }
+    
+    #[test]
+    fn test_args_with_source_map() {
+        let args = Args::try_parse_from(["shrup", "--source-map", "input.sh", "output.sh"]).unwrap();
+        assert!(args.source_map);
+        assert!(!args.runtime_origins);
+        
+        let args = Args::try_parse_from(["shrup", "--runtime-origins", "input.sh", "output.sh"]).unwrap();
+        assert!(args.runtime_origins);
+    }
+    
+    #[test]
+    fn test_args_with_depfile() {
+        let args = Args::try_parse_from(["shrup", "input.sh", "output.sh"]).unwrap();
+        assert_eq!(args.depfile, None);
+        assert_eq!(args.make_depfile, None);
+        
+        let args = Args::try_parse_from(["shrup", "--depfile", "build/output.d", "input.sh", "output.sh"]).unwrap();
+        assert_eq!(args.depfile, Some(PathBuf::from("build/output.d")));
+        
+        let args = Args::try_parse_from(["shrup", "-MD", "-D", "X", "input.sh", "output.sh"]).unwrap();
+        assert_eq!(args.make_depfile.as_deref(), Some("D"));
+        assert_eq!(args.resolve.defines, vec![("X".to_string(), "1".to_string())]);
+        
+        assert!(Args::try_parse_from(["shrup", "-MF", "input.sh", "output.sh"]).is_err());
+        assert!(Args::try_parse_from(["shrup", "-MD", "--depfile", "x.d", "input.sh", "output.sh"]).is_err());
+    }
+    
+    #[test]
+    fn test_args_with_skip_unchanged() {
+        let args = Args::try_parse_from(["shrup", "input.sh", "output.sh"]).unwrap();
+        assert!(!args.skip_unchanged);
+        
+        let args = Args::try_parse_from(["shrup", "--skip-unchanged", "input.sh", "output.sh"]).unwrap();
+        assert!(args.skip_unchanged);
+    }
+    
+    #[test]
+    fn test_args_with_mode() {
+        let args = Args::try_parse_from(["shrup", "--mode", "755", "input.sh", "output.sh"]).unwrap();
+        assert_eq!(args.mode, Some(0o755));
+        
+        let args = Args::try_parse_from(["shrup", "--mode", "0640", "input.sh", "output.sh"]).unwrap();
+        assert_eq!(args.mode, Some(0o640));
+        assert!(Args::try_parse_from(["shrup", "--mode", "rwx", "input.sh", "output.sh"]).is_err());
+        assert!(Args::try_parse_from(["shrup", "--mode", "17777", "input.sh", "output.sh"]).is_err());
+    }
+    
+    #[test]
+    fn test_args_with_defines() {
+        let args = Args::try_parse_from(["shrup", "-D", "DEBUG", "-DTARGET=alpine", "input.sh", "output.sh"]).unwrap();
+        assert_eq!(args.resolve.defines, vec![
+            ("DEBUG".to_string(), "1".to_string()),
+            ("TARGET".to_string(), "alpine".to_string()),
+        ]);
+        assert!(Args::try_parse_from(["shrup", "-D", "1BAD", "input.sh", "output.sh"]).is_err());
+        assert!(Args::try_parse_from(["shrup", "-D", "debug", "input.sh", "output.sh"]).is_err());
     }
     
     #[test]
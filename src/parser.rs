@@ -1,10 +1,13 @@
 //! Include directive parser
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+
 use crate::error::{PreprocessorError, Result};
 
 /// Quote types for include directives
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum IncludeQuoteType {
     /// <filepath>
     AngleBrackets,
@@ -27,6 +30,8 @@ pub struct IncludeDirective {
     pub source_file: PathBuf,
     /// Quote type used
     pub quote_type: IncludeQuoteType,
+    /// Include only if not already included (`#include_once`)
+    pub include_once: bool,
 }
 
 impl IncludeDirective {
@@ -42,42 +47,367 @@ impl IncludeDirective {
             file_path,
             source_file,
             quote_type,
+            include_once: false,
+        }
+    }
+    
+    /// Mark directive as include-once
+    pub fn with_include_once(mut self, include_once: bool) -> Self {
+        self.include_once = include_once;
+        self
+    }
+}
+
+impl std::fmt::Display for IncludeDirective {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let keyword = if self.include_once { "#include_once" } else { "#include" };
+        match self.quote_type {
+            IncludeQuoteType::AngleBrackets => write!(f, "{} <{}>", keyword, self.file_path),
+            IncludeQuoteType::DoubleQuotes => write!(f, "{} \"{}\"", keyword, self.file_path),
+            IncludeQuoteType::SingleQuotes => write!(f, "{} '{}'", keyword, self.file_path),
+            IncludeQuoteType::None => write!(f, "{} {}", keyword, self.file_path),
         }
     }
 }
 
+/// Preprocessor directive kinds
+#[derive(Debug, Clone, PartialEq)]
+pub enum DirectiveKind {
+    /// `#include` / `#include_once`
+    Include(IncludeDirective),
+    /// `#pragma once`
+    PragmaOnce,
+    /// `#define NAME [value]` / `#define NAME(params) value`
+    Define {
+        name: String,
+        /// Parameter names for function-like macros
+        params: Option<Vec<String>>,
+        value: String,
+    },
+    /// `#undef NAME`
+    Undef { name: String },
+    /// `#ifdef NAME`
+    Ifdef { name: String },
+    /// `#ifndef NAME`
+    Ifndef { name: String },
+    /// `#if expression` (parsed when reached in an active block)
+    If { condition: String },
+    /// `#elif expression` (parsed when reached in an active block)
+    Elif { condition: String },
+    /// `#else`
+    Else,
+    /// `#endif`
+    Endif,
+}
+
+/// Parsed directive line
+#[derive(Debug, Clone, PartialEq)]
+pub struct Directive {
+    /// Line number (1-indexed)
+    pub line_number: usize,
+    /// Directive text (trimmed line)
+    pub text: String,
+    /// Directive kind
+    pub kind: DirectiveKind,
+}
+
+/// Conditional block frame
+#[derive(Debug)]
+struct ConditionalFrame {
+    /// Opening directive line number
+    line_number: usize,
+    /// Opening directive text
+    directive: String,
+    /// Whether enclosing block is active
+    parent_active: bool,
+    /// Whether a branch of this block has been taken
+    branch_taken: bool,
+    /// Whether current branch is active
+    active: bool,
+    /// Whether `#else` has been seen
+    in_else: bool,
+}
+
+/// Stack of nested conditional blocks within a single file
+#[derive(Debug)]
+pub struct ConditionalStack {
+    /// File being processed (for error reporting)
+    source_file: PathBuf,
+    /// Open blocks, innermost last
+    frames: Vec<ConditionalFrame>,
+}
+
+impl ConditionalStack {
+    /// Create empty stack for file
+    pub fn new(source_file: &Path) -> Self {
+        Self {
+            source_file: source_file.to_path_buf(),
+            frames: Vec::new(),
+        }
+    }
+    
+    /// Check if lines at current position are kept
+    pub fn is_active(&self) -> bool {
+        self.frames.last().is_none_or(|frame| frame.active)
+    }
+    
+    /// Open block (`#if` / `#ifdef` / `#ifndef`)
+    pub fn enter(&mut self, condition: bool, directive: &Directive) {
+        let parent_active = self.is_active();
+        let active = parent_active && condition;
+        
+        self.frames.push(ConditionalFrame {
+            line_number: directive.line_number,
+            directive: directive.text.clone(),
+            parent_active,
+            branch_taken: active,
+            active,
+            in_else: false,
+        });
+    }
+    
+    /// Switch to `#elif` branch, evaluating condition only if no branch was taken
+    pub fn else_if<F>(&mut self, directive: &Directive, condition: F) -> Result<()>
+    where
+        F: FnOnce() -> Result<bool>,
+    {
+        match self.frames.last() {
+            Some(frame) if !frame.in_else => {
+                let active = frame.parent_active && !frame.branch_taken && condition()?;
+                if let Some(frame) = self.frames.last_mut() {
+                    frame.active = active;
+                    frame.branch_taken |= active;
+                }
+                Ok(())
+            }
+            _ => Err(self.unmatched(directive)),
+        }
+    }
+    
+    /// Switch to `#else` branch
+    pub fn else_branch(&mut self, directive: &Directive) -> Result<()> {
+        match self.frames.last_mut() {
+            Some(frame) if !frame.in_else => {
+                frame.in_else = true;
+                frame.active = frame.parent_active && !frame.branch_taken;
+                frame.branch_taken = true;
+                Ok(())
+            }
+            _ => Err(self.unmatched(directive)),
+        }
+    }
+    
+    /// Close block (`#endif`)
+    pub fn exit(&mut self, directive: &Directive) -> Result<()> {
+        match self.frames.pop() {
+            Some(_) => Ok(()),
+            None => Err(self.unmatched(directive)),
+        }
+    }
+    
+    /// Check all blocks are closed at end of file
+    pub fn finish(&self) -> Result<()> {
+        match self.frames.last() {
+            Some(frame) => Err(PreprocessorError::UnterminatedConditional {
+                path: self.source_file.clone(),
+                line_number: frame.line_number,
+                directive: frame.directive.clone(),
+            }.into()),
+            None => Ok(()),
+        }
+    }
+    
+    /// Error for directive without matching block
+    fn unmatched(&self, directive: &Directive) -> anyhow::Error {
+        PreprocessorError::UnmatchedConditional {
+            path: self.source_file.clone(),
+            line_number: directive.line_number,
+            directive: directive.text.clone(),
+        }.into()
+    }
+}
+
 /// Include directive parser
 pub struct IncludeParser;
 
 impl IncludeParser {
     /// Parse all include directives from content
-    pub fn parse_includes(content: &str, source_file: &PathBuf) -> Result<Vec<IncludeDirective>> {
+    pub fn parse_includes(content: &str, source_file: &Path) -> Result<Vec<IncludeDirective>> {
+        let directives = Self::parse_directives(content, source_file)?
+            .into_iter()
+            .filter_map(|directive| match directive.kind {
+                DirectiveKind::Include(include) => Some(include),
+                _ => None,
+            })
+            .collect();
+        
+        Ok(directives)
+    }
+    
+    /// Parse all preprocessor directives from content
+    pub fn parse_directives(content: &str, source_file: &Path) -> Result<Vec<Directive>> {
         let mut directives = Vec::new();
         
         for (line_number, line) in content.lines().enumerate() {
             let line_number = line_number + 1;
             let trimmed = line.trim();
             
-            if let Some(directive) = Self::parse_single_include(trimmed, line_number, source_file)? {
-                directives.push(directive);
+            if let Some(kind) = Self::parse_directive(trimmed, line_number, source_file)? {
+                directives.push(Directive {
+                    line_number,
+                    text: trimmed.to_string(),
+                    kind,
+                });
             }
         }
         
         Ok(directives)
     }
     
+    /// Parse single line for any directive
+    fn parse_directive(
+        line: &str,
+        line_number: usize,
+        source_file: &Path,
+    ) -> Result<Option<DirectiveKind>> {
+        if line.starts_with("#include") {
+            let include = Self::parse_single_include(line, line_number, source_file)?;
+            return Ok(include.map(DirectiveKind::Include));
+        }
+        
+        // Directive keyword must directly follow `#` (`# define` is a comment),
+        // and lines not matching the directive grammar are comments too
+        let Some((keyword, rest)) = Self::split_keyword(line) else {
+            return Ok(None);
+        };
+        
+        let invalid = || PreprocessorError::InvalidDirective {
+            path: source_file.to_path_buf(),
+            line_number,
+            directive: line.to_string(),
+        };
+        
+        let kind = match keyword {
+            // Unknown pragmas are left in the output
+            "pragma" if rest == "once" => DirectiveKind::PragmaOnce,
+            "define" => match Self::parse_define(rest) {
+                Some(kind) => kind.ok_or_else(invalid)?,
+                None => return Ok(None),
+            },
+            "if" | "elif" => {
+                let condition = rest.to_string();
+                if keyword == "if" {
+                    DirectiveKind::If { condition }
+                } else {
+                    DirectiveKind::Elif { condition }
+                }
+            }
+            "undef" | "ifdef" | "ifndef" => {
+                if !Self::is_symbol_name(rest) {
+                    return Ok(None);
+                }
+                let name = rest.to_string();
+                match keyword {
+                    "undef" => DirectiveKind::Undef { name },
+                    "ifdef" => DirectiveKind::Ifdef { name },
+                    _ => DirectiveKind::Ifndef { name },
+                }
+            }
+            "else" | "endif" => {
+                // Allow trailing shell comment (`#endif # DEBUG`)
+                if !rest.is_empty() && !rest.starts_with('#') {
+                    return Ok(None);
+                }
+                if keyword == "else" {
+                    DirectiveKind::Else
+                } else {
+                    DirectiveKind::Endif
+                }
+            }
+            _ => return Ok(None),
+        };
+        
+        Ok(Some(kind))
+    }
+    
+    /// Parse `#define` arguments (`NAME value` or `NAME(a, b) value`)
+    ///
+    /// Returns `None` if the line is not a define (`#define colors below`)
+    /// and `Some(None)` for a malformed parameter list.
+    fn parse_define(rest: &str) -> Option<Option<DirectiveKind>> {
+        let name_end = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+        let (name, after) = rest.split_at(name_end);
+        if !Self::is_symbol_name(name) {
+            return None;
+        }
+        
+        // Parameter list must directly follow the name, as in C
+        let (params, value) = if let Some(after_paren) = after.strip_prefix('(') {
+            let Some((params, value)) = after_paren.split_once(')') else {
+                return Some(None);
+            };
+            let params: Vec<String> = if params.trim().is_empty() {
+                Vec::new()
+            } else {
+                params.split(',').map(|param| param.trim().to_string()).collect()
+            };
+            
+            let all_valid = params.iter().all(|param| Self::is_identifier(param));
+            let unique = params.iter().enumerate().all(|(i, param)| !params[..i].contains(param));
+            if !all_valid || !unique {
+                return Some(None);
+            }
+            (Some(params), value)
+        } else if after.is_empty() || after.starts_with(char::is_whitespace) {
+            (None, after)
+        } else {
+            return None;
+        };
+        
+        Some(Some(DirectiveKind::Define {
+            name: name.to_string(),
+            params,
+            value: value.trim().to_string(),
+        }))
+    }
+    
+    /// Split `#keyword rest` into keyword and trimmed rest
+    fn split_keyword(line: &str) -> Option<(&str, &str)> {
+        let body = line.strip_prefix('#')?;
+        let end = body.find(char::is_whitespace).unwrap_or(body.len());
+        Some((&body[..end], body[end..].trim()))
+    }
+    
+    /// Check if name is a valid identifier (macro parameters)
+    pub fn is_identifier(name: &str) -> bool {
+        let mut chars = name.chars();
+        chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    }
+    
+    /// Check if name is a valid symbol name (upper case, `DEBUG`, `LOG_LEVEL`)
+    pub fn is_symbol_name(name: &str) -> bool {
+        Self::is_identifier(name) && !name.chars().any(|c| c.is_ascii_lowercase())
+    }
+    
     /// Parse single line for include directive
     fn parse_single_include(
         line: &str,
         line_number: usize,
-        source_file: &PathBuf,
+        source_file: &Path,
     ) -> Result<Option<IncludeDirective>> {
         // Skip non-include lines
         if !line.starts_with("#include") {
             return Ok(None);
         }
         
-        let after_include = line.strip_prefix("#include")
+        let (keyword, include_once) = if line.starts_with("#include_once") {
+            ("#include_once", true)
+        } else {
+            ("#include", false)
+        };
+        
+        let after_include = line.strip_prefix(keyword)
             .ok_or_else(|| PreprocessorError::InvalidIncludeDirective {
                 line_number,
                 directive: line.to_string(),
@@ -101,9 +431,9 @@ impl IncludeParser {
         Ok(Some(IncludeDirective::new(
             line_number,
             file_path,
-            source_file.clone(),
+            source_file.to_path_buf(),
             quote_type,
-        )))
+        ).with_include_once(include_once)))
     }
     
     /// Extract filepath and quote type
@@ -181,4 +511,141 @@ mod tests {
         assert_eq!(IncludeParser::extract_filepath_and_quote_type("\"\""), None);
         assert_eq!(IncludeParser::extract_filepath_and_quote_type("''"), None);
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn test_include_directive_display() {
+        let source = PathBuf::from("main.sh");
+        let content = "#include <a.sh>\n#include \"b.sh\"\n#include_once 'c.sh'\n#include d.sh";
+        let texts: Vec<_> = IncludeParser::parse_includes(content, &source).unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(texts, content.lines().collect::<Vec<_>>());
+    }
+    
+    #[test]
+    fn test_parse_include_once() {
+        let source = PathBuf::from("main.sh");
+        let directives = IncludeParser::parse_includes("#include_once \"lib.sh\"\n#include lib.sh", &source).unwrap();
+        assert_eq!(directives.len(), 2);
+        assert!(directives[0].include_once);
+        assert_eq!(directives[0].file_path, "lib.sh");
+        assert!(!directives[1].include_once);
+    }
+    
+    #[test]
+    fn test_parse_pragma_once() {
+        let source = PathBuf::from("main.sh");
+        let directives = IncludeParser::parse_directives("#!/bin/bash\n  #pragma  once\n#pragma onceish\n# pragma once", &source).unwrap();
+        assert_eq!(directives.len(), 1);
+        assert_eq!(directives[0].line_number, 2);
+        assert_eq!(directives[0].kind, DirectiveKind::PragmaOnce);
+    }
+    
+    #[test]
+    fn test_parse_conditional_directives() {
+        let source = PathBuf::from("main.sh");
+        let content = "#define DEBUG\n#define PREFIX /usr/local\n#ifdef DEBUG\n# ifdef is a comment\n#else # not debug\n#endif\n#undef DEBUG";
+        let kinds: Vec<_> = IncludeParser::parse_directives(content, &source).unwrap()
+            .into_iter()
+            .map(|directive| directive.kind)
+            .collect();
+        
+        assert_eq!(kinds, vec![
+            DirectiveKind::Define { name: "DEBUG".to_string(), params: None, value: String::new() },
+            DirectiveKind::Define { name: "PREFIX".to_string(), params: None, value: "/usr/local".to_string() },
+            DirectiveKind::Ifdef { name: "DEBUG".to_string() },
+            DirectiveKind::Else,
+            DirectiveKind::Endif,
+            DirectiveKind::Undef { name: "DEBUG".to_string() },
+        ]);
+    }
+    
+    #[test]
+    fn test_parse_function_define() {
+        let source = PathBuf::from("main.sh");
+        let directives = IncludeParser::parse_directives("#define LOG(level, msg) echo \"[@level@] @msg@\"\n#define NOW() date\n#define PAREN (x)", &source).unwrap();
+        let kinds: Vec<_> = directives.into_iter().map(|directive| directive.kind).collect();
+        
+        assert_eq!(kinds, vec![
+            DirectiveKind::Define {
+                name: "LOG".to_string(),
+                params: Some(vec!["level".to_string(), "msg".to_string()]),
+                value: "echo \"[@level@] @msg@\"".to_string(),
+            },
+            DirectiveKind::Define { name: "NOW".to_string(), params: Some(Vec::new()), value: "date".to_string() },
+            // Space before `(` makes an object-like macro
+            DirectiveKind::Define { name: "PAREN".to_string(), params: None, value: "(x)".to_string() },
+        ]);
+    }
+    
+    #[test]
+    fn test_parse_invalid_directives() {
+        let source = PathBuf::from("main.sh");
+        assert!(IncludeParser::parse_directives("#define F(a, a) x", &source).is_err());
+        assert!(IncludeParser::parse_directives("#define F(a b) x", &source).is_err());
+        assert!(IncludeParser::parse_directives("#define F(a x", &source).is_err());
+    }
+    
+    #[test]
+    fn test_parse_comment_like_lines() {
+        let source = PathBuf::from("main.sh");
+        let content = concat!(
+            "#else branch below\n",
+            "#endif DEBUG\n",
+            "#define colors used below\n",
+            "#define A-B\n",
+            "#define 1X\n",
+            "#ifdef\n",
+            "#ifndef A B\n",
+            "#undef old settings\n",
+        );
+        
+        assert!(IncludeParser::parse_directives(content, &source).unwrap().is_empty());
+    }
+    
+    #[test]
+    fn test_parse_if_directives() {
+        let source = PathBuf::from("main.sh");
+        let directives = IncludeParser::parse_directives("#if VERSION >= 3\n#elif defined(X)\n#iffy", &source).unwrap();
+        assert_eq!(directives.len(), 2);
+        assert_eq!(directives[0].kind, DirectiveKind::If { condition: "VERSION >= 3".to_string() });
+        assert_eq!(directives[1].kind, DirectiveKind::Elif { condition: "defined(X)".to_string() });
+    }
+    
+    #[test]
+    fn test_conditional_stack() {
+        let directive = |line_number: usize, text: &str| Directive {
+            line_number,
+            text: text.to_string(),
+            kind: DirectiveKind::Else,
+        };
+        let mut stack = ConditionalStack::new(Path::new("main.sh"));
+        assert!(stack.is_active());
+        
+        stack.enter(false, &directive(1, "#ifdef A"));
+        assert!(!stack.is_active());
+        
+        // Nested block inside inactive branch stays inactive
+        stack.enter(true, &directive(2, "#ifdef B"));
+        assert!(!stack.is_active());
+        stack.else_branch(&directive(3, "#else")).unwrap();
+        assert!(!stack.is_active());
+        stack.exit(&directive(4, "#endif")).unwrap();
+        
+        stack.else_if(&directive(5, "#elif X"), || Ok(true)).unwrap();
+        assert!(stack.is_active());
+        // Later branches are skipped without evaluating
+        stack.else_if(&directive(5, "#elif Y"), || panic!("evaluated")).unwrap();
+        assert!(!stack.is_active());
+        stack.else_branch(&directive(5, "#else")).unwrap();
+        assert!(!stack.is_active());
+        assert!(stack.else_branch(&directive(6, "#else")).is_err());
+        assert!(stack.else_if(&directive(6, "#elif Z"), || Ok(true)).is_err());
+        
+        assert!(stack.finish().is_err());
+        stack.exit(&directive(7, "#endif")).unwrap();
+        assert!(stack.finish().is_ok());
+        assert!(stack.exit(&directive(8, "#endif")).is_err());
+    }
+}
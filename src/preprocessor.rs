@@ -1,11 +1,30 @@
 //! Main preprocessor functionality
 
+use std::fs;
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use anyhow::Context;
 
-use crate::error::Result;
-use crate::parser::{IncludeParser, IncludeDirective};
-use crate::resolver::{ProcessingConfig, ProcessingContext, FileResolver};
+use crate::depfile::Depfile;
+use crate::error::{PreprocessorError, Result};
+use crate::expr::Expr;
+use crate::filesystem::FileSystem;
+use crate::graph::IncludeGraph;
+use crate::parser::{ConditionalStack, Directive, DirectiveKind, IncludeParser, IncludeDirective};
+use crate::runtime;
+use crate::sourcemap::{LineOrigin, MappedContent, SourceMap};
+use crate::resolver::{
+    DuplicateIncludePolicy, FileResolver, InputEncoding, LineEndings, ProcessingConfig, ProcessingContext,
+    ShebangPolicy, SymlinkPolicy,
+};
+
+/// Path meaning stdin (input) or stdout (output)
+pub const STDIO_PATH: &str = "-";
+
+/// File name of stdin input in the base directory (`__FILE__`, error messages)
+pub const STDIN_FILE_NAME: &str = "<stdin>";
 
 /// Shell script preprocessor
 pub struct ShellPreprocessor {
@@ -19,64 +38,223 @@ impl ShellPreprocessor {
     }
     
     /// Process file and resolve includes
+    ///
+    /// `-` as input reads stdin as a virtual `<stdin>` file in the base
+    /// directory, and `-` as output writes to stdout.
     pub fn process_file(&self, input_path: &Path, output_path: &Path) -> Result<()> {
-        let mut context = ProcessingContext::new(self.config.clone());
-        
         // Read input file
-        let input_content = FileResolver::read_file_content(input_path)
-            .with_context(|| format!("Failed to read input file: {}", input_path.display()))?;
+        let (input_content, source_path) = self.read_input(input_path)?;
         
         // Process file content
-        let processed_content = self.process_content(&input_content, input_path, &mut context)?;
+        let (processed, context) = self.process_mapped(&input_content, &source_path)?;
+        let (processed_content, mut source_map) = self.finish(processed, &context);
         
         // Write output
-        std::fs::write(output_path, processed_content)
-            .with_context(|| format!("Failed to write output file: {}", output_path.display()))?;
+        if output_path == Path::new(STDIO_PATH) {
+            io::stdout().lock().write_all(processed_content.as_bytes())
+                .context("Failed to write to stdout")?;
+            return Ok(());
+        }
+        
+        self.write_output(output_path, processed_content.as_bytes(), self.output_permissions(input_path))?;
+        
+        if self.config.source_map {
+            source_map.file = output_path.file_name().map(|name| name.to_string_lossy().into_owned());
+            self.write_output(&SourceMap::path_for(output_path), source_map.to_json().as_bytes(), None)?;
+        }
+        
+        if let Some(depfile_path) = &self.config.depfile {
+            let depfile = Depfile::new(output_path, self.dependencies(&context));
+            self.write_output(depfile_path, depfile.render().as_bytes(), None)?;
+        }
+        
+        Ok(())
+    }
+    
+    /// Write output file, skipping unchanged content if configured
+    fn write_output(&self, output_path: &Path, content: &[u8], permissions: Option<fs::Permissions>) -> Result<()> {
+        if self.config.skip_unchanged_output && fs::read(output_path).is_ok_and(|existing| existing == content) {
+            // Keep mtime so downstream build steps don't re-run
+            if let Some(permissions) = permissions {
+                fs::set_permissions(output_path, permissions)
+                    .with_context(|| format!("Failed to set permissions: {}", output_path.display()))?;
+            }
+            return Ok(());
+        }
+        
+        write_atomic(output_path, content, permissions)
+            .with_context(|| format!("Failed to write output file: {}", output_path.display()))
+    }
+    
+    /// Permissions for output file: configured mode, else the input file's (Unix only)
+    #[cfg(unix)]
+    fn output_permissions(&self, input_path: &Path) -> Option<fs::Permissions> {
+        use std::os::unix::fs::PermissionsExt;
+        
+        let mode = self.config.output_mode.or_else(|| {
+            if input_path == Path::new(STDIO_PATH) {
+                return None;
+            }
+            self.config.file_system.mode(input_path)
+        })?;
+        Some(fs::Permissions::from_mode(mode))
+    }
+    
+    /// Permissions for output file (left to the platform default)
+    #[cfg(not(unix))]
+    fn output_permissions(&self, _input_path: &Path) -> Option<fs::Permissions> {
+        None
+    }
+    
+    /// Process file (or `-` for stdin) and write result to writer
+    pub fn process_file_to_writer<W: Write>(&self, input_path: &Path, writer: W) -> Result<()> {
+        let (input_content, input_path) = self.read_input(input_path)?;
+        self.process_to_writer(&input_content, &input_path, writer)
+    }
+    
+    /// Process in-memory script content and write result to writer
+    ///
+    /// `source_path` is treated as in `process_str`. The output is built in
+    /// memory and written in one piece once processing succeeds, so a
+    /// failed include leaves nothing half-written in the writer.
+    pub fn process_to_writer<W: Write>(&self, content: &str, source_path: &Path, mut writer: W) -> Result<()> {
+        let processed_content = self.process_str(content, source_path)?;
+        
+        writer.write_all(processed_content.as_bytes())
+            .context("Failed to write output")?;
+        writer.flush()
+            .context("Failed to write output")?;
         
         Ok(())
     }
     
+    /// Process in-memory script content
+    ///
+    /// `source_path` need not exist: relative includes resolve against its
+    /// directory, and it is reported as `__FILE__` and in errors.
+    pub fn process_str(&self, content: &str, source_path: &Path) -> Result<String> {
+        self.process_str_with_source_map(content, source_path)
+            .map(|(processed_content, _)| processed_content)
+    }
+    
+    /// Process in-memory script content, mapping output lines to their sources
+    pub fn process_str_with_source_map(&self, content: &str, source_path: &Path) -> Result<(String, SourceMap)> {
+        let (processed, context) = self.process_mapped(content, source_path)?;
+        Ok(self.finish(processed, &context))
+    }
+    
+    /// Resolve includes of file (or `-` for stdin) without writing output
+    ///
+    /// Includes are resolved as in a real run, so `-D` symbols and
+    /// conditional blocks decide which files are part of the graph.
+    pub fn include_graph(&self, input_path: &Path) -> Result<IncludeGraph> {
+        let (input_content, input_path) = self.read_input(input_path)?;
+        let (_, context) = self.process_mapped(&input_content, &input_path)?;
+        Ok(IncludeGraph::new(context.sources().to_vec(), context.includes().to_vec()))
+    }
+    
+    /// Process root content and return it with the final context
+    fn process_mapped(&self, content: &str, source_path: &Path) -> Result<(MappedContent, ProcessingContext)> {
+        let mut context = ProcessingContext::new(self.config.clone());
+        context.set_root_shebang(shebang_line(content));
+        
+        let processed = self.process_content(content, source_path, None, true, &mut context)?;
+        Ok((processed, context))
+    }
+    
+    /// Apply output options to processed content and build its source map
+    fn finish(&self, mut processed: MappedContent, context: &ProcessingContext) -> (String, SourceMap) {
+        if self.config.runtime_origins {
+            processed = runtime::insert_origin_table(processed, context.sources());
+        }
+        let source_map = SourceMap::new(None, context.sources().to_vec(), processed.origins);
+        
+        (self.config.line_endings.apply(processed.text), source_map)
+    }
+    
+    /// Files the output was generated from (virtual stdin excluded)
+    fn dependencies(&self, context: &ProcessingContext) -> Vec<PathBuf> {
+        context.visited_files()
+            .into_iter()
+            .filter(|path| self.config.file_system.is_file(path))
+            .map(Path::to_path_buf)
+            .collect()
+    }
+    
+    /// Read input file (or stdin) and return its content and source path
+    fn read_input(&self, input_path: &Path) -> Result<(String, PathBuf)> {
+        if input_path != Path::new(STDIO_PATH) {
+            let content = FileResolver::read_file_content(input_path, &self.config)
+                .with_context(|| format!("Failed to read input file: {}", input_path.display()))?;
+            return Ok((content, input_path.to_path_buf()));
+        }
+        
+        let mut bytes = Vec::new();
+        io::stdin().lock().read_to_end(&mut bytes)
+            .context("Failed to read stdin")?;
+        
+        let virtual_path = self.config.base_directory.join(STDIN_FILE_NAME);
+        let content = FileResolver::decode_content(&bytes, &virtual_path, self.config.input_encoding)?;
+        Ok((content, virtual_path))
+    }
+    
     /// Process content and resolve includes recursively
+    ///
+    /// Each kept line is emitted with its original line ending, so a final
+    /// newline (or its absence) and CRLF endings survive unchanged.
     fn process_content(
         &self,
         content: &str,
         source_file: &Path,
+        search_root: Option<PathBuf>,
+        keep_shebang: bool,
         context: &mut ProcessingContext,
-    ) -> Result<String> {
+    ) -> Result<MappedContent> {
         // Enter file in context
-        context.enter_file(source_file)?;
+        context.enter_file(source_file, search_root)?;
+        
+        // Parse directives
+        let directives = IncludeParser::parse_directives(content, source_file)?;
         
-        // Parse include directives
-        let includes = IncludeParser::parse_includes(content, &source_file.to_path_buf())?;
+        let drop_shebang = !keep_shebang && shebang_line(content).is_some();
+        let display_file = context.config().display_path(source_file).to_string_lossy().into_owned();
+        let source = context.source_index(&display_file);
         
-        let result = if includes.is_empty() {
-            // No includes, return as-is
-            content.to_string()
+        let result = if directives.is_empty() && !content.contains('@') && !drop_shebang {
+            // No directives or macro references, return as-is
+            MappedContent::verbatim(content, source)
         } else {
-            // Process file lines and replace includes
-            let mut result = String::new();
-            let lines: Vec<&str> = content.lines().collect();
+            // Process file lines, replacing includes and dropping inactive blocks
+            let mut directives = directives.into_iter().peekable();
+            let mut conditionals = ConditionalStack::new(source_file);
+            let mut output = MappedContent::new();
             
-            for (line_index, line) in lines.iter().enumerate() {
+            for (line_index, line) in content.split_inclusive('\n').enumerate() {
                 let line_number = line_index + 1;
+                let (line, ending) = split_line_ending(line);
+                context.set_location(&display_file, line_number);
                 
-                // Check for include directive
-                if let Some(include) = includes.iter().find(|inc| inc.line_number == line_number) {
-                    // Replace with included content
-                    let included_content = self.process_include(include, context)?;
-                    result.push_str(&included_content);
-                } else {
-                    // Regular line
-                    result.push_str(line);
+                if line_number == 1 && drop_shebang {
+                    continue;
                 }
                 
-                // Add newline except last line
-                if line_index < lines.len() - 1 {
-                    result.push('\n');
+                if let Some(directive) = directives.next_if(|d| d.line_number == line_number) {
+                    self.process_directive(directive, ending, source_file, &mut conditionals, &mut output, context)?;
+                } else if conditionals.is_active() {
+                    // Regular line
+                    let expanded = context.macros().expand(line)
+                        .map_err(|message| PreprocessorError::MacroExpansion {
+                            path: source_file.to_path_buf(),
+                            line_number,
+                            message,
+                        })?;
+                    output.push_line(&expanded, ending, Some(LineOrigin { source, line: line_number }));
                 }
             }
             
-            result
+            conditionals.finish()?;
+            
+            output
         };
         
         // Exit file from context
@@ -85,43 +263,218 @@ impl ShellPreprocessor {
         Ok(result)
     }
     
+    /// Process single directive line
+    fn process_directive(
+        &self,
+        directive: Directive,
+        ending: &str,
+        source_file: &Path,
+        conditionals: &mut ConditionalStack,
+        output: &mut MappedContent,
+        context: &mut ProcessingContext,
+    ) -> Result<()> {
+        match &directive.kind {
+            // Conditionals are tracked even inside inactive blocks to keep nesting
+            DirectiveKind::Ifdef { name } => conditionals.enter(context.is_defined(name), &directive),
+            DirectiveKind::Ifndef { name } => conditionals.enter(!context.is_defined(name), &directive),
+            DirectiveKind::If { condition } => {
+                // Expressions inside inactive blocks are not parsed or evaluated
+                let active = conditionals.is_active()
+                    && Self::evaluate_condition(condition, &directive, source_file, context)?;
+                conditionals.enter(active, &directive);
+            }
+            DirectiveKind::Elif { condition } => conditionals.else_if(&directive, || {
+                Self::evaluate_condition(condition, &directive, source_file, context)
+            })?,
+            DirectiveKind::Else => conditionals.else_branch(&directive)?,
+            DirectiveKind::Endif => conditionals.exit(&directive)?,
+            _ if !conditionals.is_active() => {}
+            DirectiveKind::Include(include) => output.append(self.process_include(include, ending, context)?),
+            DirectiveKind::PragmaOnce => context.mark_include_once(source_file)?,
+            DirectiveKind::Define { name, params: None, value } => context.define(name.clone(), value.clone()),
+            DirectiveKind::Define { name, params: Some(params), value } => {
+                context.define_function(name.clone(), params.clone(), value.clone());
+            }
+            DirectiveKind::Undef { name } => context.undefine(name),
+        }
+        
+        Ok(())
+    }
+    
+    /// Parse and evaluate `#if` / `#elif` condition against defined symbols
+    fn evaluate_condition(
+        condition: &str,
+        directive: &Directive,
+        source_file: &Path,
+        context: &ProcessingContext,
+    ) -> Result<bool> {
+        let value = Expr::parse(condition)
+            .and_then(|expr| expr.evaluate(&|name| context.symbol(name)))
+            .map_err(|message| PreprocessorError::InvalidExpression {
+                path: source_file.to_path_buf(),
+                line_number: directive.line_number,
+                directive: directive.text.clone(),
+                message,
+            })?;
+        
+        Ok(value.is_truthy())
+    }
+    
+    /// Decide whether to keep an included file's shebang
+    fn check_shebang(
+        directive: &IncludeDirective,
+        resolved_path: &Path,
+        content: &str,
+        context: &ProcessingContext,
+    ) -> Result<bool> {
+        let Some(shebang) = shebang_line(content) else {
+            return Ok(true);
+        };
+        
+        match context.config().shebangs {
+            ShebangPolicy::Keep => Ok(true),
+            ShebangPolicy::Drop => Ok(false),
+            ShebangPolicy::Error => {
+                let interpreter = shebang_interpreter(shebang);
+                if let Some(root_interpreter) = context.root_shebang().map(shebang_interpreter) {
+                    if interpreter != root_interpreter {
+                        return Err(PreprocessorError::ShebangConflict {
+                            path: resolved_path.to_path_buf(),
+                            interpreter: interpreter.to_string(),
+                            root_interpreter: root_interpreter.to_string(),
+                            directive: directive.to_string(),
+                            including_file: directive.source_file.clone(),
+                            line_number: directive.line_number,
+                        }.into());
+                    }
+                }
+                Ok(false)
+            }
+        }
+    }
+    
     /// Process single include directive
+    ///
+    /// The directive line's ending terminates the included block, so an
+    /// included file without a final newline does not glue to the next
+    /// line of the parent.
     fn process_include(
         &self,
         directive: &IncludeDirective,
+        ending: &str,
         context: &mut ProcessingContext,
-    ) -> Result<String> {
+    ) -> Result<MappedContent> {
+        // Line break between generated lines (directive may be the unterminated last line)
+        let line_break = if ending.is_empty() { "\n" } else { ending };
+        
         // Resolve file path
-        let resolved_path = FileResolver::resolve_include_path(directive, context.config())?;
+        let candidate = FileResolver::resolve_include(directive, context.search_root(), context.config())?;
+        let search_root = candidate.search_root(context.search_root());
+        let resolved_path = candidate.path;
+        context.record_include(directive, &resolved_path);
+        
+        // Generate output with debug comments
+        let mut result = MappedContent::new();
+        
+        // Skip files already inlined (include once)
+        if !context.should_include(&resolved_path, directive.include_once)? {
+            if context.config().debug_mode {
+                result.push_line(&FileResolver::generate_skip_comment(&resolved_path), ending, None);
+            }
+            return Ok(result);
+        }
         
         // Read included content
-        let included_content = FileResolver::read_file_content(&resolved_path)?;
+        let included_content = FileResolver::read_file_content(&resolved_path, context.config())?;
         
-        // Process file included content recursively
-        let processed_included = self.process_content(&included_content, &resolved_path, context)?;
+        // Apply shebang policy
+        let keep_shebang = Self::check_shebang(directive, &resolved_path, &included_content, context)?;
         
-        // Generate output with debug comments
-        let mut result = String::new();
+        // Process file included content recursively
+        let mut processed_included = self.process_content(&included_content, &resolved_path, search_root, keep_shebang, context)?;
         
         if context.config().debug_mode {
-            result.push_str(&FileResolver::generate_include_comment(&resolved_path, true));
-            result.push('\n');
+            result.push_line(&FileResolver::generate_include_comment(&resolved_path, true), line_break, None);
         }
         
-        result.push_str(&processed_included);
+        // Terminate last included line
+        processed_included.terminate(if context.config().debug_mode { line_break } else { ending });
+        result.append(processed_included);
         
         if context.config().debug_mode {
-            // Add newline before end comment
-            if !processed_included.ends_with('\n') {
-                result.push('\n');
-            }
-            result.push_str(&FileResolver::generate_include_comment(&resolved_path, false));
+            result.push_line(&FileResolver::generate_include_comment(&resolved_path, false), ending, None);
         }
         
         Ok(result)
     }
 }
 
+/// Write file atomically via temp file in the same directory and rename
+///
+/// Readers never see a partially written file, and the previous content
+/// survives a failed write. Without `permissions`, an existing file keeps
+/// its own (e.g. `+x` of a previous bundle).
+fn write_atomic(path: &Path, content: &[u8], permissions: Option<fs::Permissions>) -> io::Result<()> {
+    // Unique per process and call
+    static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+    
+    let file_name = path.file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "output path has no file name"))?;
+    let temp_name = format!(
+        ".{}.{}-{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    let temp_path = path.with_file_name(temp_name);
+    let permissions = permissions.or_else(|| fs::metadata(path).ok().map(|metadata| metadata.permissions()));
+    
+    let result = (|| {
+        let mut file = fs::OpenOptions::new().write(true).create_new(true).open(&temp_path)?;
+        file.write_all(content)?;
+        if let Some(permissions) = permissions {
+            file.set_permissions(permissions)?;
+        }
+        file.sync_all()?;
+        fs::rename(&temp_path, path)
+    })();
+    
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Get shebang line (`#!...`) of content, if any
+fn shebang_line(content: &str) -> Option<&str> {
+    let first_line = content.lines().next()?;
+    first_line.starts_with("#!").then(|| first_line.trim_end())
+}
+
+/// Get interpreter name of shebang (`#!/usr/bin/env bash` is `bash`)
+fn shebang_interpreter(shebang: &str) -> &str {
+    let mut words = shebang.trim_start_matches("#!").split_whitespace();
+    let program = words.next().unwrap_or_default();
+    let name = program.rsplit('/').next().unwrap_or(program);
+    
+    if name == "env" {
+        // Skip env options (`env -S bash -e`)
+        words.find(|word| !word.starts_with('-')).unwrap_or(name)
+    } else {
+        name
+    }
+}
+
+/// Split line into text and line ending (`\r\n`, `\n` or none)
+fn split_line_ending(line: &str) -> (&str, &str) {
+    if let Some(text) = line.strip_suffix("\r\n") {
+        (text, "\r\n")
+    } else if let Some(text) = line.strip_suffix('\n') {
+        (text, "\n")
+    } else {
+        (line, "")
+    }
+}
 
 /// Builder for preprocessor config
 pub struct PreprocessorBuilder {
@@ -154,6 +507,106 @@ impl PreprocessorBuilder {
         self
     }
     
+    /// Set root directory includes must stay within (defaults to base directory)
+    pub fn root_directory<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.config.root_directory = Some(path.into());
+        self
+    }
+    
+    /// Allow includes outside the root directory
+    pub fn allow_outside_root(mut self, allowed: bool) -> Self {
+        self.config.allow_outside_root = allowed;
+        self
+    }
+    
+    /// Set policy for symbolic links in include paths
+    pub fn symlinks(mut self, policy: SymlinkPolicy) -> Self {
+        self.config.symlinks = policy;
+        self
+    }
+    
+    /// Add include search directory (like `-I`)
+    pub fn include_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.config.include_paths.push(path.into());
+        self
+    }
+    
+    /// Set library search directories (like `SHRUP_PATH`)
+    pub fn library_paths<I, P>(mut self, paths: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<PathBuf>,
+    {
+        self.config.library_paths = paths.into_iter().map(Into::into).collect();
+        self
+    }
+    
+    /// Set output line ending handling
+    pub fn line_endings(mut self, line_endings: LineEndings) -> Self {
+        self.config.line_endings = line_endings;
+        self
+    }
+    
+    /// Set encoding for source files without BOM or `coding:` comment
+    pub fn input_encoding(mut self, input_encoding: InputEncoding) -> Self {
+        self.config.input_encoding = input_encoding;
+        self
+    }
+    
+    /// Set filesystem includes are resolved and read from
+    pub fn file_system(mut self, file_system: impl FileSystem + 'static) -> Self {
+        self.config.file_system = Arc::new(file_system);
+        self
+    }
+    
+    /// Write source map sidecar (`output.sh.map`) next to output file
+    pub fn source_map(mut self, source_map: bool) -> Self {
+        self.config.source_map = source_map;
+        self
+    }
+    
+    /// Embed line-origin table and lookup helpers in output
+    pub fn runtime_origins(mut self, runtime_origins: bool) -> Self {
+        self.config.runtime_origins = runtime_origins;
+        self
+    }
+    
+    /// Write Make/Ninja dependency file of output and included files
+    pub fn depfile<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.config.depfile = Some(path.into());
+        self
+    }
+    
+    /// Skip writing output whose content is unchanged (keeps mtime)
+    pub fn skip_unchanged_output(mut self, skip_unchanged_output: bool) -> Self {
+        self.config.skip_unchanged_output = skip_unchanged_output;
+        self
+    }
+    
+    /// Set permission bits of output file (Unix; ignored elsewhere)
+    pub fn output_mode(mut self, mode: u32) -> Self {
+        self.config.output_mode = Some(mode);
+        self
+    }
+    
+    /// Set policy for shebangs of included files
+    pub fn shebangs(mut self, shebangs: ShebangPolicy) -> Self {
+        self.config.shebangs = shebangs;
+        self
+    }
+    
+    /// Set policy for files included more than once
+    pub fn duplicate_includes(mut self, policy: DuplicateIncludePolicy) -> Self {
+        self.config.duplicate_includes = policy;
+        self
+    }
+    
+    /// Predefine macro (like `-D NAME=VALUE`)
+    pub fn define<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        self.config.defines.insert(name.into(), value.into());
+        self
+    }
+    
     /// Build preprocessor
     pub fn build(self) -> ShellPreprocessor {
         ShellPreprocessor::new(self.config)
@@ -169,7 +622,8 @@ impl Default for PreprocessorBuilder {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs;
+    use crate::filesystem::MemoryFileSystem;
+    use crate::parser::IncludeQuoteType;
     use tempfile::TempDir;
     
     #[test]
@@ -262,4 +716,917 @@ mod tests {
         assert!(result.contains("echo \"middle\""));
         assert!(result.contains("echo \"main\""));
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn test_diamond_include() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        // main.sh -> a.sh -> log.sh, main.sh -> b.sh -> log.sh
+        fs::write(temp_dir.path().join("log.sh"), "echo \"log\"").unwrap();
+        fs::write(temp_dir.path().join("a.sh"), "#include log.sh\necho \"a\"").unwrap();
+        fs::write(temp_dir.path().join("b.sh"), "#include log.sh\necho \"b\"").unwrap();
+        let main_path = temp_dir.path().join("main.sh");
+        fs::write(&main_path, "#include a.sh\n#include b.sh").unwrap();
+        
+        let output_path = temp_dir.path().join("output.sh");
+        
+        // Inline every time (default)
+        let preprocessor = PreprocessorBuilder::new()
+            .base_directory(temp_dir.path())
+            .build();
+        preprocessor.process_file(&main_path, &output_path).unwrap();
+        let result = fs::read_to_string(&output_path).unwrap();
+        assert_eq!(result.matches("echo \"log\"").count(), 2);
+        
+        // Inline once
+        let preprocessor = PreprocessorBuilder::new()
+            .base_directory(temp_dir.path())
+            .duplicate_includes(DuplicateIncludePolicy::Once)
+            .build();
+        preprocessor.process_file(&main_path, &output_path).unwrap();
+        let result = fs::read_to_string(&output_path).unwrap();
+        assert_eq!(result.matches("echo \"log\"").count(), 1);
+        assert!(result.contains("echo \"a\""));
+        assert!(result.contains("echo \"b\""));
+        
+        // Error
+        let preprocessor = PreprocessorBuilder::new()
+            .base_directory(temp_dir.path())
+            .duplicate_includes(DuplicateIncludePolicy::Error)
+            .build();
+        let err = preprocessor.process_file(&main_path, &output_path).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<crate::error::PreprocessorError>(),
+            Some(crate::error::PreprocessorError::DuplicateInclude { .. })
+        ));
+    }
+    
+    #[test]
+    fn test_circular_include() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        fs::write(temp_dir.path().join("a.sh"), "#include b.sh").unwrap();
+        fs::write(temp_dir.path().join("b.sh"), "#include a.sh").unwrap();
+        let main_path = temp_dir.path().join("main.sh");
+        fs::write(&main_path, "#include a.sh").unwrap();
+        
+        let output_path = temp_dir.path().join("output.sh");
+        
+        let preprocessor = PreprocessorBuilder::new()
+            .base_directory(temp_dir.path())
+            .duplicate_includes(DuplicateIncludePolicy::Once)
+            .build();
+        let err = preprocessor.process_file(&main_path, &output_path).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<crate::error::PreprocessorError>(),
+            Some(crate::error::PreprocessorError::CircularDependency { .. })
+        ));
+    }
+    
+    #[test]
+    fn test_library_sibling_includes() {
+        let temp_dir = TempDir::new().unwrap();
+        let project_dir = temp_dir.path().join("project");
+        let shared_dir = temp_dir.path().join("shared");
+        fs::create_dir_all(shared_dir.join("util")).unwrap();
+        fs::create_dir(&project_dir).unwrap();
+        
+        fs::write(shared_dir.join("net.sh"), "#include \"log.sh\"\necho \"net\"\n").unwrap();
+        fs::write(shared_dir.join("log.sh"), "#include util/fmt.sh\necho \"log\"\n").unwrap();
+        fs::write(shared_dir.join("util/fmt.sh"), "echo \"fmt\"\n").unwrap();
+        fs::write(temp_dir.path().join("secret.sh"), "echo \"secret\"\n").unwrap();
+        let main_path = project_dir.join("main.sh");
+        fs::write(&main_path, "#include <net.sh>\n").unwrap();
+        let output_path = project_dir.join("output.sh");
+        
+        // Libraries found through -I or SHRUP_PATH include their own siblings
+        for builder in [
+            PreprocessorBuilder::new().include_path(&shared_dir),
+            PreprocessorBuilder::new().library_paths([&shared_dir]),
+        ] {
+            let preprocessor = builder.base_directory(&project_dir).build();
+            preprocessor.process_file(&main_path, &output_path).unwrap();
+            let result = fs::read_to_string(&output_path).unwrap();
+            assert_eq!(result, "echo \"fmt\"\necho \"log\"\necho \"net\"\n");
+        }
+        
+        // ...but stay confined to their search directory
+        fs::write(shared_dir.join("util/fmt.sh"), "#include ../../secret.sh\n").unwrap();
+        let preprocessor = PreprocessorBuilder::new()
+            .base_directory(&project_dir)
+            .include_path(&shared_dir)
+            .build();
+        let err = preprocessor.process_file(&main_path, &output_path).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<crate::error::PreprocessorError>(),
+            Some(crate::error::PreprocessorError::PathOutsideRoot { .. })
+        ));
+    }
+    
+    #[test]
+    fn test_pragma_once() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        fs::write(temp_dir.path().join("log.sh"), "#pragma once\necho \"log\"").unwrap();
+        fs::write(temp_dir.path().join("a.sh"), "#include log.sh\necho \"a\"").unwrap();
+        let main_path = temp_dir.path().join("main.sh");
+        fs::write(&main_path, "#include a.sh\n#include log.sh\necho \"main\"").unwrap();
+        
+        let output_path = temp_dir.path().join("output.sh");
+        
+        let preprocessor = PreprocessorBuilder::new()
+            .base_directory(temp_dir.path())
+            .build();
+        preprocessor.process_file(&main_path, &output_path).unwrap();
+        
+        let result = fs::read_to_string(&output_path).unwrap();
+        assert_eq!(result, "echo \"log\"\necho \"a\"\necho \"main\"");
+        
+        // Debug mode leaves a marker for the dropped include
+        let preprocessor = PreprocessorBuilder::new()
+            .debug_mode(true)
+            .base_directory(temp_dir.path())
+            .build();
+        preprocessor.process_file(&main_path, &output_path).unwrap();
+        
+        let result = fs::read_to_string(&output_path).unwrap();
+        assert!(result.contains("(already included)"));
+        assert!(!result.contains("#pragma once"));
+    }
+    
+    #[test]
+    fn test_include_once_directive() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        fs::write(temp_dir.path().join("lib.sh"), "echo \"lib\"").unwrap();
+        let main_path = temp_dir.path().join("main.sh");
+        fs::write(&main_path, "#include lib.sh\n#include_once lib.sh\n#include lib.sh").unwrap();
+        
+        let output_path = temp_dir.path().join("output.sh");
+        
+        let preprocessor = PreprocessorBuilder::new()
+            .base_directory(temp_dir.path())
+            .build();
+        preprocessor.process_file(&main_path, &output_path).unwrap();
+        
+        let result = fs::read_to_string(&output_path).unwrap();
+        assert_eq!(result.matches("echo \"lib\"").count(), 2);
+    }
+    
+    #[test]
+    fn test_conditional_blocks() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        fs::write(temp_dir.path().join("debug.sh"), "echo \"debug helpers\"").unwrap();
+        let main_path = temp_dir.path().join("main.sh");
+        fs::write(&main_path, "\
+#ifdef DEBUG
+#include debug.sh
+#ifndef QUIET
+echo \"verbose\"
+#endif
+#else
+echo \"release\"
+#endif
+#define FEATURE
+#ifdef FEATURE
+echo \"feature\"
+#endif
+#undef FEATURE
+#ifdef FEATURE
+echo \"undefined feature\"
+#endif").unwrap();
+        
+        let output_path = temp_dir.path().join("output.sh");
+        
+        let preprocessor = PreprocessorBuilder::new()
+            .base_directory(temp_dir.path())
+            .build();
+        preprocessor.process_file(&main_path, &output_path).unwrap();
+        let result = fs::read_to_string(&output_path).unwrap();
+        assert_eq!(result, "echo \"release\"\necho \"feature\"\n");
+        
+        let preprocessor = PreprocessorBuilder::new()
+            .base_directory(temp_dir.path())
+            .define("DEBUG", "1")
+            .build();
+        preprocessor.process_file(&main_path, &output_path).unwrap();
+        let result = fs::read_to_string(&output_path).unwrap();
+        assert_eq!(result, "echo \"debug helpers\"\necho \"verbose\"\necho \"feature\"\n");
+    }
+    
+    #[test]
+    fn test_unbalanced_conditionals() {
+        let temp_dir = TempDir::new().unwrap();
+        let output_path = temp_dir.path().join("output.sh");
+        
+        let preprocessor = PreprocessorBuilder::new()
+            .base_directory(temp_dir.path())
+            .build();
+        
+        let lib_path = temp_dir.path().join("lib.sh");
+        fs::write(&lib_path, "echo \"lib\"\n#ifdef DEBUG\necho \"debug\"").unwrap();
+        let main_path = temp_dir.path().join("main.sh");
+        fs::write(&main_path, "#include lib.sh\n#endif").unwrap();
+        
+        // Blocks cannot span files
+        let err = preprocessor.process_file(&main_path, &output_path).unwrap_err();
+        match err.downcast_ref::<crate::error::PreprocessorError>() {
+            Some(crate::error::PreprocessorError::UnterminatedConditional { path, line_number, .. }) => {
+                assert_eq!(path, &lib_path);
+                assert_eq!(*line_number, 2);
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+        
+        fs::write(&main_path, "echo \"main\"\n#else").unwrap();
+        let err = preprocessor.process_file(&main_path, &output_path).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<crate::error::PreprocessorError>(),
+            Some(crate::error::PreprocessorError::UnmatchedConditional { line_number: 2, .. })
+        ));
+    }
+    
+    #[test]
+    fn test_comment_like_lines() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        let preprocessor = PreprocessorBuilder::new()
+            .base_directory(temp_dir.path())
+            .build();
+        
+        // Lines not matching the directive grammar are kept as comments
+        let content = "\
+#ifdef DEBUG
+echo \"debug\"
+#else branch below
+echo \"still debug\"
+#endif
+#define colors used below
+#endif of setup
+echo \"@colors@\"
+";
+        let result = preprocessor.process_str(content, &temp_dir.path().join("main.sh")).unwrap();
+        assert_eq!(result, "#define colors used below\n#endif of setup\necho \"@colors@\"\n");
+    }
+    
+    #[test]
+    fn test_if_expressions() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        let main_path = temp_dir.path().join("main.sh");
+        fs::write(&main_path, "\
+#if TARGET == \"alpine\" && VERSION >= 3
+echo \"new alpine\"
+#elif TARGET == \"alpine\"
+echo \"old alpine\"
+#elif defined(TARGET)
+echo \"other\"
+#else
+echo \"default\"
+#endif").unwrap();
+        
+        let output_path = temp_dir.path().join("output.sh");
+        
+        for (target, version, expected) in [
+            (Some("alpine"), "3", "echo \"new alpine\"\n"),
+            (Some("alpine"), "2", "echo \"old alpine\"\n"),
+            (Some("debian"), "3", "echo \"other\"\n"),
+            (None, "3", "echo \"default\"\n"),
+        ] {
+            let mut builder = PreprocessorBuilder::new()
+                .base_directory(temp_dir.path())
+                .define("VERSION", version);
+            if let Some(target) = target {
+                builder = builder.define("TARGET", target);
+            }
+            builder.build().process_file(&main_path, &output_path).unwrap();
+            
+            let result = fs::read_to_string(&output_path).unwrap();
+            assert_eq!(result, expected);
+        }
+        
+        // Parse and evaluation errors report file and line
+        let preprocessor = PreprocessorBuilder::new()
+            .base_directory(temp_dir.path())
+            .define("TARGET", "alpine")
+            .build();
+        for content in ["echo\n#if TARGET > 1\n#endif", "echo\n#if VERSION >=\n#endif", "#if 0\n#elif\n#endif"] {
+            fs::write(&main_path, content).unwrap();
+            let err = preprocessor.process_file(&main_path, &output_path).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<PreprocessorError>(),
+                Some(PreprocessorError::InvalidExpression { line_number: 2, .. })
+            ));
+        }
+        
+        // Malformed expressions in inactive blocks are never parsed
+        fs::write(&main_path, "\
+#if 0
+#if the file exists we go on
+#endif
+#elif 1
+echo \"done\"
+#elif 1 || (
+#endif").unwrap();
+        preprocessor.process_file(&main_path, &output_path).unwrap();
+        assert_eq!(fs::read_to_string(&output_path).unwrap(), "echo \"done\"\n");
+    }
+    
+    #[test]
+    fn test_object_macros() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        fs::write(temp_dir.path().join("install.sh"), "cp app \"@BINDIR@/app\"").unwrap();
+        let main_path = temp_dir.path().join("main.sh");
+        fs::write(&main_path, "\
+#define BINDIR @PREFIX@/bin
+echo \"v@VERSION@\"
+#include install.sh
+#undef VERSION
+echo \"@VERSION@ user@host\"").unwrap();
+        
+        let output_path = temp_dir.path().join("output.sh");
+        
+        let preprocessor = PreprocessorBuilder::new()
+            .base_directory(temp_dir.path())
+            .define("VERSION", "1.2.3")
+            .define("PREFIX", "/opt/app")
+            .build();
+        preprocessor.process_file(&main_path, &output_path).unwrap();
+        
+        let result = fs::read_to_string(&output_path).unwrap();
+        assert_eq!(result, "echo \"v1.2.3\"\ncp app \"/opt/app/bin/app\"\necho \"@VERSION@ user@host\"");
+    }
+    
+    #[test]
+    fn test_function_macros() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        fs::write(temp_dir.path().join("log.sh"), "#define LOG(level, msg) echo \"[@level@] @msg@\" >&2").unwrap();
+        let main_path = temp_dir.path().join("main.sh");
+        fs::write(&main_path, "#include log.sh\n@LOG(info, deploying $host to stage 1)@").unwrap();
+        
+        let output_path = temp_dir.path().join("output.sh");
+        
+        let preprocessor = PreprocessorBuilder::new()
+            .base_directory(temp_dir.path())
+            .build();
+        preprocessor.process_file(&main_path, &output_path).unwrap();
+        
+        let result = fs::read_to_string(&output_path).unwrap();
+        assert_eq!(result, "echo \"[info] deploying $host to stage 1\" >&2");
+        
+        // Wrong argument count reports location
+        fs::write(&main_path, "#include log.sh\n\n@LOG(info)@").unwrap();
+        let err = preprocessor.process_file(&main_path, &output_path).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<PreprocessorError>(),
+            Some(PreprocessorError::MacroExpansion { line_number: 3, .. })
+        ));
+    }
+    
+    #[test]
+    fn test_predefined_macros() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        fs::create_dir(temp_dir.path().join("lib")).unwrap();
+        fs::write(temp_dir.path().join("lib/net.sh"), "\n#define HERE @__FILE__@:@__LINE__@\necho \"@HERE@\"").unwrap();
+        let main_path = temp_dir.path().join("main.sh");
+        fs::write(&main_path, "#include lib/net.sh\necho \"@__FILE__@:@__LINE__@ v@__SHRUP_VERSION__@\"").unwrap();
+        
+        let output_path = temp_dir.path().join("output.sh");
+        
+        let preprocessor = PreprocessorBuilder::new()
+            .base_directory(temp_dir.path())
+            .build();
+        preprocessor.process_file(&main_path, &output_path).unwrap();
+        
+        let result = fs::read_to_string(&output_path).unwrap();
+        let expected = format!(
+            "\necho \"lib/net.sh:3\"\necho \"main.sh:2 v{}\"",
+            env!("CARGO_PKG_VERSION")
+        );
+        assert_eq!(result, expected);
+    }
+    
+    #[test]
+    fn test_trailing_newlines() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        fs::write(temp_dir.path().join("with_newline.sh"), "echo \"a\"\n").unwrap();
+        fs::write(temp_dir.path().join("without_newline.sh"), "echo \"b\"").unwrap();
+        fs::write(temp_dir.path().join("empty.sh"), "").unwrap();
+        let main_path = temp_dir.path().join("main.sh");
+        fs::write(&main_path, "#include with_newline.sh\n#include without_newline.sh\n#include empty.sh\necho \"main\"\n").unwrap();
+        
+        let output_path = temp_dir.path().join("output.sh");
+        
+        let preprocessor = PreprocessorBuilder::new()
+            .base_directory(temp_dir.path())
+            .build();
+        preprocessor.process_file(&main_path, &output_path).unwrap();
+        
+        let result = fs::read_to_string(&output_path).unwrap();
+        assert_eq!(result, "echo \"a\"\necho \"b\"\necho \"main\"\n");
+        
+        // Include on unterminated last line keeps the included file's final newline state
+        fs::write(&main_path, "echo \"main\"\n#include without_newline.sh").unwrap();
+        preprocessor.process_file(&main_path, &output_path).unwrap();
+        let result = fs::read_to_string(&output_path).unwrap();
+        assert_eq!(result, "echo \"main\"\necho \"b\"");
+    }
+    
+    #[test]
+    fn test_line_endings() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        fs::write(temp_dir.path().join("dos.sh"), "echo \"dos\"\r\necho \"dos2\"").unwrap();
+        let main_path = temp_dir.path().join("main.sh");
+        fs::write(&main_path, "#!/bin/sh\n#include dos.sh\r\necho \"unix\"\n").unwrap();
+        
+        let output_path = temp_dir.path().join("output.sh");
+        
+        for (line_endings, expected) in [
+            (LineEndings::Preserve, "#!/bin/sh\necho \"dos\"\r\necho \"dos2\"\r\necho \"unix\"\n"),
+            (LineEndings::Lf, "#!/bin/sh\necho \"dos\"\necho \"dos2\"\necho \"unix\"\n"),
+            (LineEndings::Crlf, "#!/bin/sh\r\necho \"dos\"\r\necho \"dos2\"\r\necho \"unix\"\r\n"),
+        ] {
+            let preprocessor = PreprocessorBuilder::new()
+                .base_directory(temp_dir.path())
+                .line_endings(line_endings)
+                .build();
+            preprocessor.process_file(&main_path, &output_path).unwrap();
+            
+            let result = fs::read_to_string(&output_path).unwrap();
+            assert_eq!(result, expected, "{:?}", line_endings);
+        }
+    }
+    
+    #[test]
+    fn test_input_encodings() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        fs::write(temp_dir.path().join("bom.sh"), b"\xEF\xBB\xBFecho \"bom\"\n").unwrap();
+        fs::write(temp_dir.path().join("legacy.sh"), b"echo \"caf\xE9\"\n").unwrap();
+        let main_path = temp_dir.path().join("main.sh");
+        fs::write(&main_path, b"\xEF\xBB\xBF#!/bin/sh\n#include bom.sh\n#include legacy.sh\n").unwrap();
+        
+        let output_path = temp_dir.path().join("output.sh");
+        
+        // Latin-1 file is rejected as UTF-8
+        let preprocessor = PreprocessorBuilder::new()
+            .base_directory(temp_dir.path())
+            .build();
+        let err = preprocessor.process_file(&main_path, &output_path).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<PreprocessorError>(),
+            Some(PreprocessorError::InvalidEncoding { path, .. }) if path.ends_with("legacy.sh")
+        ));
+        
+        let preprocessor = PreprocessorBuilder::new()
+            .base_directory(temp_dir.path())
+            .input_encoding("latin1".parse().unwrap())
+            .build();
+        preprocessor.process_file(&main_path, &output_path).unwrap();
+        
+        let result = fs::read_to_string(&output_path).unwrap();
+        assert_eq!(result, "#!/bin/sh\necho \"bom\"\necho \"caf\u{e9}\"\n");
+    }
+    
+    #[test]
+    fn test_shebang_policies() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        fs::write(temp_dir.path().join("posix.sh"), "#!/bin/sh\necho \"posix\"\n").unwrap();
+        fs::write(temp_dir.path().join("bash.sh"), "#!/usr/bin/env bash\necho \"bash\"\n").unwrap();
+        let main_path = temp_dir.path().join("main.sh");
+        fs::write(&main_path, "#!/bin/sh -e\n#include posix.sh\n#include bash.sh\n").unwrap();
+        
+        let output_path = temp_dir.path().join("output.sh");
+        
+        let process = |shebangs| {
+            PreprocessorBuilder::new()
+                .base_directory(temp_dir.path())
+                .shebangs(shebangs)
+                .build()
+                .process_file(&main_path, &output_path)
+                .map(|()| fs::read_to_string(&output_path).unwrap())
+        };
+        
+        assert_eq!(process(ShebangPolicy::Drop).unwrap(), "#!/bin/sh -e\necho \"posix\"\necho \"bash\"\n");
+        assert_eq!(
+            process(ShebangPolicy::Keep).unwrap(),
+            "#!/bin/sh -e\n#!/bin/sh\necho \"posix\"\n#!/usr/bin/env bash\necho \"bash\"\n"
+        );
+        
+        let err = process(ShebangPolicy::Error).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<PreprocessorError>(),
+            Some(PreprocessorError::ShebangConflict { interpreter, root_interpreter, line_number: 3, .. })
+                if interpreter == "bash" && root_interpreter == "sh"
+        ));
+    }
+    
+    #[test]
+    fn test_shebang_interpreter() {
+        assert_eq!(shebang_interpreter("#!/bin/sh"), "sh");
+        assert_eq!(shebang_interpreter("#! /bin/bash -eu"), "bash");
+        assert_eq!(shebang_interpreter("#!/usr/bin/env bash"), "bash");
+        assert_eq!(shebang_interpreter("#!/usr/bin/env -S zsh -f"), "zsh");
+    }
+    
+    #[test]
+    fn test_virtual_stdin_source() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        fs::write(temp_dir.path().join("lib.sh"), "echo \"lib\"\n").unwrap();
+        
+        let preprocessor = PreprocessorBuilder::new()
+            .base_directory(temp_dir.path())
+            .build();
+        let mut context = ProcessingContext::new(preprocessor.config.clone());
+        
+        // Relative includes of stdin resolve against the base directory
+        let stdin_path = temp_dir.path().join(STDIN_FILE_NAME);
+        let result = preprocessor
+            .process_content("#include lib.sh\necho @__FILE__@\n", &stdin_path, None, true, &mut context)
+            .unwrap();
+        assert_eq!(result.text, "echo \"lib\"\necho <stdin>\n");
+    }
+    
+    #[test]
+    fn test_process_str() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        fs::create_dir(temp_dir.path().join("src")).unwrap();
+        fs::write(temp_dir.path().join("src/lib.sh"), "echo \"lib\"\n").unwrap();
+        
+        let preprocessor = PreprocessorBuilder::new()
+            .base_directory(temp_dir.path())
+            .build();
+        
+        // Relative includes resolve against the virtual file's directory
+        let virtual_path = temp_dir.path().join("src/generated.sh");
+        let result = preprocessor
+            .process_str("#include lib.sh\necho @__FILE__@\n", &virtual_path)
+            .unwrap();
+        assert_eq!(result, "echo \"lib\"\necho src/generated.sh\n");
+        
+    }
+    
+    #[test]
+    fn test_process_to_writer() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        fs::write(temp_dir.path().join("lib.sh"), "echo \"lib\"\n").unwrap();
+        let main_path = temp_dir.path().join("main.sh");
+        fs::write(&main_path, "#!/bin/sh\n#include lib.sh\n").unwrap();
+        
+        let preprocessor = PreprocessorBuilder::new()
+            .base_directory(temp_dir.path())
+            .build();
+        
+        let mut output = Vec::new();
+        preprocessor.process_to_writer("#include lib.sh\necho \"main\"\n", &main_path, &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "echo \"lib\"\necho \"main\"\n");
+        
+        let mut output = Vec::new();
+        preprocessor.process_file_to_writer(&main_path, &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "#!/bin/sh\necho \"lib\"\n");
+        
+        // Nothing is written when processing fails
+        let mut output = Vec::new();
+        assert!(preprocessor.process_to_writer("echo \"main\"\n#include missing.sh\n", &main_path, &mut output).is_err());
+        assert!(output.is_empty());
+    }
+    
+    #[test]
+    fn test_memory_file_system() {
+        let mut file_system = MemoryFileSystem::new();
+        file_system
+            .add_file("/project/main.sh", "#!/bin/sh\n#include <log.sh>\n#include lib/util.sh\n")
+            .add_file("/project/lib/util.sh", "#pragma once\necho \"util\"\n")
+            .add_file("/vendor/log.sh", "echo \"log\"\n")
+            .add_symlink("/project/linked.sh", "lib/util.sh");
+        
+        let preprocessor = PreprocessorBuilder::new()
+            .base_directory("/project")
+            .include_path("/vendor")
+            .file_system(file_system.clone())
+            .build();
+        
+        let mut output = Vec::new();
+        preprocessor.process_file_to_writer(Path::new("/project/main.sh"), &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "#!/bin/sh\necho \"log\"\necho \"util\"\n");
+        
+        // Errors and policies use the in-memory filesystem too
+        let err = preprocessor
+            .process_str("#include lib/utill.sh\n", Path::new("/project/generated.sh"))
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<PreprocessorError>(),
+            Some(PreprocessorError::IncludeNotFound { suggestion: Some(suggestion), .. }) if suggestion == "lib/util.sh"
+        ));
+        
+        let preprocessor = PreprocessorBuilder::new()
+            .base_directory("/project")
+            .symlinks(SymlinkPolicy::Refuse)
+            .file_system(file_system)
+            .build();
+        let err = preprocessor
+            .process_str("#include linked.sh\n", Path::new("/project/generated.sh"))
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<PreprocessorError>(),
+            Some(PreprocessorError::SymlinkRefused { .. })
+        ));
+    }
+    
+    #[test]
+    fn test_atomic_output() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        let main_path = temp_dir.path().join("main.sh");
+        let output_path = temp_dir.path().join("output.sh");
+        fs::write(&output_path, "previous").unwrap();
+        
+        let preprocessor = PreprocessorBuilder::new()
+            .base_directory(temp_dir.path())
+            .build();
+        
+        // Failed run keeps previous output
+        fs::write(&main_path, "#include missing.sh\n").unwrap();
+        assert!(preprocessor.process_file(&main_path, &output_path).is_err());
+        assert_eq!(fs::read_to_string(&output_path).unwrap(), "previous");
+        
+        fs::write(&main_path, "echo \"main\"\n").unwrap();
+        preprocessor.process_file(&main_path, &output_path).unwrap();
+        assert_eq!(fs::read_to_string(&output_path).unwrap(), "echo \"main\"\n");
+        
+        // Write errors leave no temp file behind
+        let missing_dir_output = temp_dir.path().join("missing/output.sh");
+        assert!(preprocessor.process_file(&main_path, &missing_dir_output).is_err());
+        
+        let mut entries: Vec<_> = fs::read_dir(temp_dir.path()).unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        entries.sort();
+        assert_eq!(entries, ["main.sh", "output.sh"]);
+    }
+    
+    #[cfg(unix)]
+    #[test]
+    fn test_output_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        
+        let temp_dir = TempDir::new().unwrap();
+        
+        let main_path = temp_dir.path().join("main.sh");
+        fs::write(&main_path, "#!/bin/sh\necho \"main\"\n").unwrap();
+        fs::set_permissions(&main_path, fs::Permissions::from_mode(0o755)).unwrap();
+        let output_path = temp_dir.path().join("output.sh");
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o7777;
+        
+        // Input permissions are copied
+        PreprocessorBuilder::new()
+            .base_directory(temp_dir.path())
+            .build()
+            .process_file(&main_path, &output_path)
+            .unwrap();
+        assert_eq!(mode(&output_path), 0o755);
+        
+        // Explicit mode wins
+        PreprocessorBuilder::new()
+            .base_directory(temp_dir.path())
+            .output_mode(0o640)
+            .build()
+            .process_file(&main_path, &output_path)
+            .unwrap();
+        assert_eq!(mode(&output_path), 0o640);
+        
+        // Input permissions come from the configured filesystem
+        let mut file_system = MemoryFileSystem::new();
+        file_system
+            .add_file("/project/deploy.sh", "echo \"deploy\"\n")
+            .set_mode("/project/deploy.sh", 0o700);
+        PreprocessorBuilder::new()
+            .base_directory("/project")
+            .file_system(file_system)
+            .build()
+            .process_file(Path::new("/project/deploy.sh"), &output_path)
+            .unwrap();
+        assert_eq!(mode(&output_path), 0o700);
+        
+        // Without an input mode the existing output keeps its permissions
+        fs::set_permissions(&output_path, fs::Permissions::from_mode(0o751)).unwrap();
+        let mut file_system = MemoryFileSystem::new();
+        file_system.add_file("/project/deploy.sh", "echo \"changed\"\n");
+        PreprocessorBuilder::new()
+            .base_directory("/project")
+            .file_system(file_system)
+            .build()
+            .process_file(Path::new("/project/deploy.sh"), &output_path)
+            .unwrap();
+        assert_eq!(fs::read_to_string(&output_path).unwrap(), "echo \"changed\"\n");
+        assert_eq!(mode(&output_path), 0o751);
+    }
+    
+    #[test]
+    fn test_skip_unchanged_output() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        let main_path = temp_dir.path().join("main.sh");
+        fs::write(&main_path, "echo \"main\"\n").unwrap();
+        let output_path = temp_dir.path().join("output.sh");
+        
+        let preprocessor = PreprocessorBuilder::new()
+            .base_directory(temp_dir.path())
+            .skip_unchanged_output(true)
+            .build();
+        preprocessor.process_file(&main_path, &output_path).unwrap();
+        
+        // Backdate output to detect rewrites
+        let old_mtime = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+        fs::File::options().write(true).open(&output_path).unwrap().set_modified(old_mtime).unwrap();
+        let mtime = || fs::metadata(&output_path).unwrap().modified().unwrap();
+        
+        preprocessor.process_file(&main_path, &output_path).unwrap();
+        assert_eq!(mtime(), old_mtime);
+        
+        fs::write(&main_path, "echo \"changed\"\n").unwrap();
+        preprocessor.process_file(&main_path, &output_path).unwrap();
+        assert_ne!(mtime(), old_mtime);
+        assert_eq!(fs::read_to_string(&output_path).unwrap(), "echo \"changed\"\n");
+    }
+    
+    #[test]
+    fn test_source_map() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        fs::create_dir(temp_dir.path().join("lib")).unwrap();
+        fs::write(temp_dir.path().join("lib/net.sh"), "#!/bin/bash\n# net helpers\nfetch() {\n  curl \"$1\"\n}").unwrap();
+        let main_path = temp_dir.path().join("main.sh");
+        fs::write(&main_path, "#!/bin/sh\n#ifdef DEBUG\nset -x\n#endif\n#include lib/net.sh\nfetch @URL@\n").unwrap();
+        
+        let output_path = temp_dir.path().join("output.sh");
+        
+        let preprocessor = PreprocessorBuilder::new()
+            .base_directory(temp_dir.path())
+            .define("URL", "example.com")
+            .source_map(true)
+            .build();
+        preprocessor.process_file(&main_path, &output_path).unwrap();
+        
+        let result = fs::read_to_string(&output_path).unwrap();
+        assert_eq!(result, "#!/bin/sh\n# net helpers\nfetch() {\n  curl \"$1\"\n}\nfetch example.com\n");
+        
+        let map_json = fs::read_to_string(temp_dir.path().join("output.sh.map")).unwrap();
+        let source_map = SourceMap::from_json(&map_json).unwrap();
+        assert_eq!(source_map.file.as_deref(), Some("output.sh"));
+        assert_eq!(source_map.lines.len(), result.lines().count());
+        
+        let lookups: Vec<_> = (1..=6).map(|line| source_map.lookup(line).unwrap()).collect();
+        assert_eq!(lookups, [
+            ("main.sh", 1),
+            ("lib/net.sh", 2),
+            ("lib/net.sh", 3),
+            ("lib/net.sh", 4),
+            ("lib/net.sh", 5),
+            ("main.sh", 6),
+        ]);
+        
+        // Debug comments are generated lines
+        let preprocessor = PreprocessorBuilder::new()
+            .base_directory(temp_dir.path())
+            .debug_mode(true)
+            .build();
+        let content = fs::read_to_string(&main_path).unwrap();
+        let (result, source_map) = preprocessor.process_str_with_source_map(&content, &main_path).unwrap();
+        assert_eq!(source_map.lines.len(), result.lines().count());
+        assert_eq!(source_map.lookup(2), None);
+        assert_eq!(source_map.lookup(3), Some(("lib/net.sh", 2)));
+        assert_eq!(source_map.lookup(7), None);
+        assert_eq!(source_map.lookup(8), Some(("main.sh", 6)));
+    }
+    
+    #[test]
+    fn test_include_graph() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        fs::create_dir(temp_dir.path().join("lib")).unwrap();
+        fs::write(temp_dir.path().join("lib/log.sh"), "#pragma once\necho log\n").unwrap();
+        fs::write(temp_dir.path().join("lib/net.sh"), "#include \"log.sh\"\n").unwrap();
+        fs::write(temp_dir.path().join("lib/debug.sh"), "set -x\n").unwrap();
+        let main_path = temp_dir.path().join("main.sh");
+        fs::write(&main_path, "#include lib/net.sh\n#include <lib/log.sh>\n#ifdef DEBUG\n#include lib/debug.sh\n#endif\n#include lib/net.sh\n").unwrap();
+        
+        let preprocessor = PreprocessorBuilder::new()
+            .base_directory(temp_dir.path())
+            .build();
+        let graph = preprocessor.include_graph(&main_path).unwrap();
+        
+        // Skipped `#pragma once` includes are edges, repeated expansions are not
+        assert_eq!(graph.files, ["main.sh", "lib/net.sh", "lib/log.sh"]);
+        let edges: Vec<_> = graph.edges.iter()
+            .map(|edge| (edge.from.as_str(), edge.to.as_str(), edge.line, edge.quote_type.clone()))
+            .collect();
+        assert_eq!(edges, [
+            ("main.sh", "lib/net.sh", 1, IncludeQuoteType::None),
+            ("lib/net.sh", "lib/log.sh", 1, IncludeQuoteType::DoubleQuotes),
+            ("main.sh", "lib/log.sh", 2, IncludeQuoteType::AngleBrackets),
+            ("main.sh", "lib/net.sh", 6, IncludeQuoteType::None),
+        ]);
+        
+        // Conditional includes follow defined symbols
+        let preprocessor = PreprocessorBuilder::new()
+            .base_directory(temp_dir.path())
+            .define("DEBUG", "1")
+            .build();
+        let graph = preprocessor.include_graph(&main_path).unwrap();
+        assert!(graph.files.contains(&"lib/debug.sh".to_string()));
+        
+        // Nothing is written
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 2);
+    }
+    
+    #[test]
+    fn test_depfile() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        fs::create_dir(temp_dir.path().join("my lib")).unwrap();
+        fs::write(temp_dir.path().join("my lib/net.sh"), "#include log.sh\n").unwrap();
+        fs::write(temp_dir.path().join("my lib/log.sh"), "echo log\n").unwrap();
+        fs::write(temp_dir.path().join("unused.sh"), "echo unused\n").unwrap();
+        let main_path = temp_dir.path().join("main.sh");
+        fs::write(&main_path, "#include \"my lib/net.sh\"\n#ifdef UNUSED\n#include unused.sh\n#endif\n").unwrap();
+        
+        let output_path = temp_dir.path().join("output.sh");
+        let depfile_path = temp_dir.path().join("output.sh.d");
+        
+        let preprocessor = PreprocessorBuilder::new()
+            .base_directory(temp_dir.path())
+            .depfile(&depfile_path)
+            .build();
+        preprocessor.process_file(&main_path, &output_path).unwrap();
+        
+        let root = temp_dir.path().canonicalize().unwrap();
+        let escaped_root = root.to_string_lossy().replace(' ', "\\ ");
+        assert_eq!(
+            fs::read_to_string(&depfile_path).unwrap(),
+            format!(
+                "{}: \\\n  {root}/main.sh \\\n  {root}/my\\ lib/log.sh \\\n  {root}/my\\ lib/net.sh\n",
+                output_path.to_string_lossy().replace(' ', "\\ "),
+                root = escaped_root,
+            )
+        );
+        
+        // Virtual stdin source is not a dependency
+        let stdin_path = temp_dir.path().join(STDIN_FILE_NAME);
+        let (_, context) = preprocessor.process_mapped("#include main.sh\n", &stdin_path).unwrap();
+        let dependencies = preprocessor.dependencies(&context);
+        assert_eq!(dependencies.len(), 3);
+        assert!(dependencies.iter().all(|path| path.starts_with(&root)));
+    }
+    
+    #[cfg(unix)]
+    #[test]
+    fn test_runtime_origins() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        fs::create_dir(temp_dir.path().join("lib")).unwrap();
+        fs::write(temp_dir.path().join("lib/net.sh"), "fetch() {\n  false\n}\n").unwrap();
+        let main_path = temp_dir.path().join("main.sh");
+        fs::write(&main_path, "\
+#!/bin/bash
+#include lib/net.sh
+set -E
+trap '__shrup_report_error $? $LINENO' ERR
+__shrup_origin $LINENO
+fetch
+").unwrap();
+        
+        let output_path = temp_dir.path().join("output.sh");
+        
+        let preprocessor = PreprocessorBuilder::new()
+            .base_directory(temp_dir.path())
+            .runtime_origins(true)
+            .source_map(true)
+            .build();
+        preprocessor.process_file(&main_path, &output_path).unwrap();
+        
+        let result = fs::read_to_string(&output_path).unwrap();
+        assert!(result.starts_with("#!/bin/bash\n# --- shrup runtime line origins ---\n"));
+        
+        // Source map accounts for the embedded table
+        let source_map = SourceMap::from_json(&fs::read_to_string(temp_dir.path().join("output.sh.map")).unwrap()).unwrap();
+        assert_eq!(source_map.lines.len(), result.lines().count());
+        
+        let output = std::process::Command::new("bash").arg(&output_path).output().unwrap();
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "main.sh:5\n");
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            "lib/net.sh:2: command failed with exit status 1\nmain.sh:6: command failed with exit status 1\n"
+        );
+    }
+}
@@ -1,12 +1,206 @@
 //! File path resolution and include processing
 
-use std::collections::HashSet;
-use std::fs;
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::OsStr;
+use std::fmt;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
 use anyhow::Context;
+use encoding_rs::{Encoding, UTF_8};
 
 use crate::error::{PreprocessorError, Result};
-use crate::parser::IncludeDirective;
+use crate::filesystem::{FileSystem, RealFileSystem};
+use crate::graph::IncludeEdge;
+use crate::macros::MacroTable;
+use crate::parser::{IncludeDirective, IncludeQuoteType};
+
+/// Policy for files included more than once without forming a cycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateIncludePolicy {
+    /// Inline the file at every include site
+    #[default]
+    Always,
+    /// Inline the file only at its first include site
+    Once,
+    /// Reject repeated includes
+    Error,
+}
+
+impl FromStr for DuplicateIncludePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(Self::Always),
+            "once" => Ok(Self::Once),
+            "error" => Ok(Self::Error),
+            _ => Err(format!("invalid duplicate include policy '{}' (expected always, once or error)", s)),
+        }
+    }
+}
+
+/// Environment variable listing shared library directories
+pub const LIBRARY_PATH_ENV: &str = "SHRUP_PATH";
+
+/// Where an include search location comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchOrigin {
+    /// Directory of the including file
+    IncludingFile,
+    /// Include path (`-I`)
+    IncludePath,
+    /// Library path (`SHRUP_PATH`)
+    LibraryPath,
+    /// Base directory (absolute includes)
+    BaseDirectory,
+}
+
+impl fmt::Display for SearchOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            SearchOrigin::IncludingFile => "including file",
+            SearchOrigin::IncludePath => "-I",
+            SearchOrigin::LibraryPath => LIBRARY_PATH_ENV,
+            SearchOrigin::BaseDirectory => "base directory",
+        };
+        f.write_str(label)
+    }
+}
+
+/// Candidate path for include resolution
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchCandidate {
+    /// Candidate file path
+    pub path: PathBuf,
+    /// Directory the include path was joined to
+    pub search_dir: PathBuf,
+    /// Search location the path was derived from
+    pub origin: SearchOrigin,
+}
+
+impl SearchCandidate {
+    /// Search directory the candidate is confined to (`None` = root directory)
+    ///
+    /// Files found through `-I` or `SHRUP_PATH` are confined to that search
+    /// directory, and their relative includes inherit it from the including file.
+    pub fn search_root(&self, including_root: Option<&Path>) -> Option<PathBuf> {
+        match self.origin {
+            SearchOrigin::IncludePath | SearchOrigin::LibraryPath => Some(self.search_dir.clone()),
+            SearchOrigin::IncludingFile => including_root.map(Path::to_path_buf),
+            SearchOrigin::BaseDirectory => None,
+        }
+    }
+}
+
+/// Policy for symbolic links met while resolving includes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SymlinkPolicy {
+    /// Follow symbolic links
+    #[default]
+    Follow,
+    /// Reject includes reached through any symbolic link
+    Refuse,
+    /// Reject symbolic links whose target escapes the allowed root
+    RefuseEscaping,
+}
+
+impl FromStr for SymlinkPolicy {
+    type Err = String;
+    
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "follow" => Ok(Self::Follow),
+            "refuse" => Ok(Self::Refuse),
+            "refuse-escaping" => Ok(Self::RefuseEscaping),
+            _ => Err(format!("invalid symlink policy '{}' (expected follow, refuse or refuse-escaping)", s)),
+        }
+    }
+}
+
+/// Output line ending handling
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineEndings {
+    /// Keep each line's original ending
+    #[default]
+    Preserve,
+    /// Convert to `\n`
+    Lf,
+    /// Convert to `\r\n`
+    Crlf,
+}
+
+impl LineEndings {
+    /// Apply line ending conversion to processed output
+    pub fn apply(self, content: String) -> String {
+        match self {
+            LineEndings::Preserve => content,
+            LineEndings::Lf => content.replace("\r\n", "\n"),
+            LineEndings::Crlf => content.replace("\r\n", "\n").replace('\n', "\r\n"),
+        }
+    }
+}
+
+impl FromStr for LineEndings {
+    type Err = String;
+    
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "preserve" => Ok(Self::Preserve),
+            "lf" => Ok(Self::Lf),
+            "crlf" => Ok(Self::Crlf),
+            _ => Err(format!("invalid line endings '{}' (expected preserve, lf or crlf)", s)),
+        }
+    }
+}
+
+/// Policy for shebang lines of included files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShebangPolicy {
+    /// Drop shebangs of included files
+    #[default]
+    Drop,
+    /// Keep shebangs of included files
+    Keep,
+    /// Drop shebangs, rejecting interpreters that differ from the root script's
+    Error,
+}
+
+impl FromStr for ShebangPolicy {
+    type Err = String;
+    
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(Self::Drop),
+            "keep" => Ok(Self::Keep),
+            "error" => Ok(Self::Error),
+            _ => Err(format!("invalid shebang policy '{}' (expected drop, keep or error)", s)),
+        }
+    }
+}
+
+/// Encoding of source files without a BOM or `coding:` comment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InputEncoding {
+    /// Decode as UTF-8
+    #[default]
+    Auto,
+    /// Decode with the given encoding
+    Explicit(&'static Encoding),
+}
+
+impl FromStr for InputEncoding {
+    type Err = String;
+    
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s == "auto" {
+            return Ok(Self::Auto);
+        }
+        Encoding::for_label(s.as_bytes())
+            .map(Self::Explicit)
+            .ok_or_else(|| format!("invalid encoding '{}' (expected auto or an encoding label such as utf-8, shift_jis, latin1)", s))
+    }
+}
 
 /// Preprocessor configuration
 #[derive(Debug, Clone)]
@@ -17,6 +211,52 @@ pub struct ProcessingConfig {
     pub max_include_depth: usize,
     /// Base directory for path resolution
     pub base_directory: PathBuf,
+    /// Directory includes must stay within (defaults to base directory)
+    pub root_directory: Option<PathBuf>,
+    /// Allow includes outside the root directory (trusted builds)
+    pub allow_outside_root: bool,
+    /// How to handle symbolic links in include paths
+    pub symlinks: SymlinkPolicy,
+    /// Include search directories (`-I`), in search order
+    pub include_paths: Vec<PathBuf>,
+    /// Library directories (`SHRUP_PATH`), searched after include paths
+    pub library_paths: Vec<PathBuf>,
+    /// How to handle files included more than once
+    pub duplicate_includes: DuplicateIncludePolicy,
+    /// How to handle shebangs of included files
+    pub shebangs: ShebangPolicy,
+    /// Output line ending handling
+    pub line_endings: LineEndings,
+    /// Default source file encoding
+    pub input_encoding: InputEncoding,
+    /// Permission bits of output file on Unix (defaults to the input file's)
+    pub output_mode: Option<u32>,
+    /// Leave output untouched when its content is unchanged
+    pub skip_unchanged_output: bool,
+    /// Write source map sidecar next to output file
+    pub source_map: bool,
+    /// Embed line-origin table and lookup helpers in output
+    pub runtime_origins: bool,
+    /// Write Make/Ninja dependency file to this path
+    pub depfile: Option<PathBuf>,
+    /// Predefined macros (`-D NAME[=VALUE]`)
+    pub defines: BTreeMap<String, String>,
+    /// Filesystem includes are resolved and read from
+    pub file_system: Arc<dyn FileSystem>,
+}
+
+impl ProcessingConfig {
+    /// Directory includes must stay within
+    pub fn root_directory(&self) -> &Path {
+        self.root_directory.as_deref().unwrap_or(&self.base_directory)
+    }
+    
+    /// Path as shown to users (relative to base directory when inside it)
+    pub fn display_path(&self, path: &Path) -> PathBuf {
+        path.strip_prefix(&self.base_directory)
+            .unwrap_or(path)
+            .to_path_buf()
+    }
 }
 
 impl Default for ProcessingConfig {
@@ -25,6 +265,22 @@ impl Default for ProcessingConfig {
             debug_mode: false,
             max_include_depth: 100,
             base_directory: PathBuf::from("."),
+            root_directory: None,
+            allow_outside_root: false,
+            symlinks: SymlinkPolicy::default(),
+            include_paths: Vec::new(),
+            library_paths: Vec::new(),
+            duplicate_includes: DuplicateIncludePolicy::default(),
+            shebangs: ShebangPolicy::default(),
+            line_endings: LineEndings::default(),
+            input_encoding: InputEncoding::default(),
+            output_mode: None,
+            skip_unchanged_output: false,
+            source_map: false,
+            runtime_origins: false,
+            depfile: None,
+            defines: BTreeMap::new(),
+            file_system: Arc::new(RealFileSystem),
         }
     }
 }
@@ -32,10 +288,22 @@ impl Default for ProcessingConfig {
 /// Processing state context
 #[derive(Debug)]
 pub struct ProcessingContext {
-    /// Files already visited (circular dependency detection)
+    /// Files already visited (duplicate include detection)
     visited_files: HashSet<PathBuf>,
+    /// Files marked with `#pragma once`
+    once_files: HashSet<PathBuf>,
     /// Stack of processing files (for error reporting)
     include_stack: Vec<PathBuf>,
+    /// Search roots of processing files (parallel to `include_stack`)
+    search_roots: Vec<Option<PathBuf>>,
+    /// Defined macros (`-D` and `#define`)
+    macros: MacroTable,
+    /// Shebang line of the root script
+    root_shebang: Option<String>,
+    /// Source files of output lines (display paths, source map order)
+    sources: Vec<String>,
+    /// Includes resolved so far (include graph edges)
+    includes: Vec<IncludeEdge>,
     /// Config
     config: ProcessingConfig,
 }
@@ -45,7 +313,13 @@ impl ProcessingContext {
     pub fn new(config: ProcessingConfig) -> Self {
         Self {
             visited_files: HashSet::new(),
+            once_files: HashSet::new(),
             include_stack: Vec::new(),
+            search_roots: Vec::new(),
+            macros: Self::initial_macros(&config),
+            root_shebang: None,
+            sources: Vec::new(),
+            includes: Vec::new(),
             config,
         }
     }
@@ -66,28 +340,54 @@ impl ProcessingContext {
         Ok(())
     }
     
-    /// Check circular dependencies
+    /// Check circular dependencies (only files on the active include stack)
     pub fn check_circular_dependency(&self, file_path: &Path) -> Result<()> {
         let canonical_path = self.canonicalize_path(file_path)?;
         
-        if self.visited_files.contains(&canonical_path) {
-            let stack_str = self.include_stack
-                .iter()
-                .map(|p| p.display().to_string())
-                .collect::<Vec<_>>()
-                .join(" -> ");
-                
+        if self.include_stack.contains(&canonical_path) {
             return Err(PreprocessorError::CircularDependency {
                 path: canonical_path,
-                stack: stack_str,
+                stack: self.include_stack_string(),
             }.into());
         }
         
         Ok(())
     }
     
+    /// Check whether file should be inlined
+    ///
+    /// Files included with `#include_once` or marked with `#pragma once` are
+    /// skipped once visited; other repeats follow the duplicate include policy.
+    pub fn should_include(&self, file_path: &Path, include_once: bool) -> Result<bool> {
+        let canonical_path = self.canonicalize_path(file_path)?;
+        let visited = self.visited_files.contains(&canonical_path);
+        
+        if visited && (include_once || self.once_files.contains(&canonical_path)) {
+            return Ok(false);
+        }
+        
+        // Cycles take precedence over duplicates
+        self.check_circular_dependency(&canonical_path)?;
+        
+        if !visited {
+            return Ok(true);
+        }
+        
+        match self.config.duplicate_includes {
+            DuplicateIncludePolicy::Always => Ok(true),
+            DuplicateIncludePolicy::Once => Ok(false),
+            DuplicateIncludePolicy::Error => Err(PreprocessorError::DuplicateInclude {
+                path: canonical_path,
+                stack: self.include_stack_string(),
+            }.into()),
+        }
+    }
+    
     /// Enter file (push to stack and visited set)
-    pub fn enter_file(&mut self, file_path: &Path) -> Result<()> {
+    ///
+    /// `search_root` is the `-I` / `SHRUP_PATH` directory the file was found
+    /// through, or `None` for files of the root directory.
+    pub fn enter_file(&mut self, file_path: &Path, search_root: Option<PathBuf>) -> Result<()> {
         let canonical_path = self.canonicalize_path(file_path)?;
         
         self.check_max_depth(&canonical_path)?;
@@ -95,13 +395,114 @@ impl ProcessingContext {
         
         self.visited_files.insert(canonical_path.clone());
         self.include_stack.push(canonical_path);
+        self.search_roots.push(search_root);
         
         Ok(())
     }
     
+    /// Mark file as include-once (`#pragma once`)
+    pub fn mark_include_once(&mut self, file_path: &Path) -> Result<()> {
+        let canonical_path = self.canonicalize_path(file_path)?;
+        self.once_files.insert(canonical_path);
+        Ok(())
+    }
+    
+    /// Define macro (`#define`)
+    pub fn define(&mut self, name: String, value: String) {
+        self.macros.define(name, value);
+    }
+    
+    /// Define function-like macro (`#define NAME(params)`)
+    pub fn define_function(&mut self, name: String, params: Vec<String>, value: String) {
+        self.macros.define_function(name, params, value);
+    }
+    
+    /// Remove macro definition (`#undef`)
+    pub fn undefine(&mut self, name: &str) {
+        self.macros.undefine(name);
+    }
+    
+    /// Check if macro is defined
+    pub fn is_defined(&self, name: &str) -> bool {
+        self.macros.is_defined(name)
+    }
+    
+    /// Get macro value
+    pub fn symbol(&self, name: &str) -> Option<&str> {
+        self.macros.get(name).map(|definition| definition.body.as_str())
+    }
+    
+    /// Get macro table
+    pub fn macros(&self) -> &MacroTable {
+        &self.macros
+    }
+    
+    /// Set source location for `__FILE__` / `__LINE__`
+    pub fn set_location(&mut self, display_file: &str, line_number: usize) {
+        self.macros.set_location(display_file, line_number);
+    }
+    
+    /// Set shebang line of the root script
+    pub fn set_root_shebang(&mut self, shebang: Option<&str>) {
+        self.root_shebang = shebang.map(str::to_string);
+    }
+    
+    /// Get shebang line of the root script
+    pub fn root_shebang(&self) -> Option<&str> {
+        self.root_shebang.as_deref()
+    }
+    
+    /// Index of source file in source map, registering it on first use
+    pub fn source_index(&mut self, display_file: &str) -> usize {
+        match self.sources.iter().position(|source| source == display_file) {
+            Some(index) => index,
+            None => {
+                self.sources.push(display_file.to_string());
+                self.sources.len() - 1
+            }
+        }
+    }
+    
+    /// Source files of output lines, in source map order
+    pub fn sources(&self) -> &[String] {
+        &self.sources
+    }
+    
+    /// Record resolved include (repeated expansions are recorded once)
+    pub fn record_include(&mut self, directive: &IncludeDirective, resolved_path: &Path) {
+        let edge = IncludeEdge {
+            from: self.config.display_path(&directive.source_file).to_string_lossy().into_owned(),
+            to: self.config.display_path(resolved_path).to_string_lossy().into_owned(),
+            line: directive.line_number,
+            quote_type: directive.quote_type.clone(),
+        };
+        
+        if !self.includes.contains(&edge) {
+            self.includes.push(edge);
+        }
+    }
+    
+    /// Includes resolved so far, in processing order
+    pub fn includes(&self) -> &[IncludeEdge] {
+        &self.includes
+    }
+    
+    /// Files processed so far (canonical paths, sorted)
+    pub fn visited_files(&self) -> Vec<&Path> {
+        let mut files: Vec<&Path> = self.visited_files.iter().map(PathBuf::as_path).collect();
+        files.sort();
+        files
+    }
+    
     /// Exit file (pop from stack, keep in visited set)
     pub fn exit_file(&mut self) {
         self.include_stack.pop();
+        self.search_roots.pop();
+    }
+    
+    /// Get search root of the current file (`None` = root directory)
+    pub fn search_root(&self) -> Option<&Path> {
+        self.search_roots.last().and_then(Option::as_deref)
     }
     
     /// Get config
@@ -109,9 +510,37 @@ impl ProcessingContext {
         &self.config
     }
     
+    /// Build macro table from built-in and predefined macros
+    fn initial_macros(config: &ProcessingConfig) -> MacroTable {
+        let mut macros = MacroTable::with_predefined();
+        for (name, value) in &config.defines {
+            macros.define(name.clone(), value.clone());
+        }
+        macros
+    }
+    
+    /// Format include stack for error messages
+    fn include_stack_string(&self) -> String {
+        self.include_stack
+            .iter()
+            .map(|p| p.display().to_string())
+            .collect::<Vec<_>>()
+            .join(" -> ")
+    }
+    
     /// Canonicalize path for consistent comparison
     fn canonicalize_path(&self, path: &Path) -> Result<PathBuf> {
-        path.canonicalize()
+        let file_system = &self.config.file_system;
+        
+        file_system.canonicalize(path)
+            .or_else(|error| {
+                // Virtual files (stdin) only need an existing parent directory
+                let (Some(parent), Some(file_name)) = (path.parent(), path.file_name()) else {
+                    return Err(error);
+                };
+                let parent = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+                file_system.canonicalize(parent).map(|parent| parent.join(file_name))
+            })
             .with_context(|| format!("Failed to canonicalize path: {}", path.display()))
     }
 }
@@ -120,44 +549,239 @@ impl ProcessingContext {
 pub struct FileResolver;
 
 impl FileResolver {
-    /// Resolve file path for include directive
+    /// Resolve file path for include directive in a file of the root directory
     pub fn resolve_include_path(
         directive: &IncludeDirective,
         config: &ProcessingConfig,
     ) -> Result<PathBuf> {
-        let include_path = Path::new(&directive.file_path);
+        Self::resolve_include(directive, None, config).map(|candidate| candidate.path)
+    }
+    
+    /// Resolve include directive of a file confined to `including_root`
+    ///
+    /// `including_root` is the search root of the including file (see
+    /// [`ProcessingContext::search_root`]).
+    pub fn resolve_include(
+        directive: &IncludeDirective,
+        including_root: Option<&Path>,
+        config: &ProcessingConfig,
+    ) -> Result<SearchCandidate> {
+        let candidates = Self::candidate_paths(directive, config);
         
-        // Absolute path: resolve relative to base directory
-        let resolved_path = if include_path.is_absolute() {
-            config.base_directory.join(include_path.strip_prefix("/").unwrap_or(include_path))
-        } else {
-            // Relative path: resolve relative to source file directory
-            let source_dir = directive.source_file
-                .parent()
-                .unwrap_or_else(|| Path::new("."));
-            source_dir.join(include_path)
-        };
+        // First existing file wins
+        if let Some(candidate) = candidates.iter().find(|candidate| config.file_system.is_file(&candidate.path)) {
+            if config.symlinks != SymlinkPolicy::Follow {
+                Self::check_symlinks(candidate, including_root, directive, config)?;
+            }
+            if !config.allow_outside_root {
+                Self::check_within_root(candidate, including_root, directive, config)?;
+            }
+            return Ok(candidate.clone());
+        }
         
-        // Check file exists
-        if !resolved_path.exists() {
-            return Err(PreprocessorError::FileNotFound {
-                path: resolved_path,
+        Err(PreprocessorError::IncludeNotFound {
+            file_path: directive.file_path.clone(),
+            directive: directive.to_string(),
+            including_file: directive.source_file.clone(),
+            line_number: directive.line_number,
+            suggestion: Self::suggest_include_path(directive, &candidates, config),
+            searched: candidates,
+        }.into())
+    }
+    
+    /// Reject resolved includes escaping their allowed root
+    ///
+    /// Files found through `-I` or `SHRUP_PATH` must stay within that
+    /// search directory, as must files found through the directory of an
+    /// including file that was itself found there. Everything else must stay
+    /// within the root directory.
+    fn check_within_root(
+        candidate: &SearchCandidate,
+        including_root: Option<&Path>,
+        directive: &IncludeDirective,
+        config: &ProcessingConfig,
+    ) -> Result<()> {
+        let canonical_root = Self::canonical_root(candidate, including_root, config)?;
+        let canonical_path = config.file_system.canonicalize(&candidate.path)
+            .with_context(|| format!("Failed to canonicalize path: {}", candidate.path.display()))?;
+        
+        if !canonical_path.starts_with(&canonical_root) {
+            return Err(PreprocessorError::PathOutsideRoot {
+                path: canonical_path,
+                root: canonical_root,
+                directive: directive.to_string(),
+                including_file: directive.source_file.clone(),
+                line_number: directive.line_number,
             }.into());
         }
         
-        // Check it's a file, not directory
-        if !resolved_path.is_file() {
-            return Err(PreprocessorError::FileNotFound {
-                path: resolved_path,
+        Ok(())
+    }
+    
+    /// Canonical root directory a candidate must stay within
+    fn canonical_root(
+        candidate: &SearchCandidate,
+        including_root: Option<&Path>,
+        config: &ProcessingConfig,
+    ) -> Result<PathBuf> {
+        let search_root = candidate.search_root(including_root);
+        let root = search_root.as_deref().unwrap_or_else(|| config.root_directory());
+        
+        config.file_system.canonicalize(root)
+            .with_context(|| format!("Failed to canonicalize root directory: {}", root.display()))
+    }
+    
+    /// Apply symlink policy to path components below the search directory
+    fn check_symlinks(
+        candidate: &SearchCandidate,
+        including_root: Option<&Path>,
+        directive: &IncludeDirective,
+        config: &ProcessingConfig,
+    ) -> Result<()> {
+        let relative = candidate.path.strip_prefix(&candidate.search_dir).unwrap_or(&candidate.path);
+        let mut current = candidate.search_dir.clone();
+        
+        for component in relative.components() {
+            current.push(component);
+            
+            if !config.file_system.is_symlink(&current) {
+                continue;
+            }
+            
+            let chain = Self::symlink_chain(&current, config.file_system.as_ref());
+            let reason = match config.symlinks {
+                SymlinkPolicy::Follow => continue,
+                SymlinkPolicy::Refuse => "symbolic links are not allowed".to_string(),
+                SymlinkPolicy::RefuseEscaping => {
+                    let canonical_root = Self::canonical_root(candidate, including_root, config)?;
+                    let target = config.file_system.canonicalize(&current)
+                        .with_context(|| format!("Failed to canonicalize path: {}", current.display()))?;
+                    if target.starts_with(&canonical_root) {
+                        continue;
+                    }
+                    format!("link target escapes {}", canonical_root.display())
+                }
+            };
+            
+            return Err(PreprocessorError::SymlinkRefused {
+                path: candidate.path.clone(),
+                reason,
+                chain,
+                directive: directive.to_string(),
+                including_file: directive.source_file.clone(),
+                line_number: directive.line_number,
             }.into());
         }
         
-        Ok(resolved_path)
+        Ok(())
+    }
+    
+    /// Follow symbolic link hops (`link -> target`) starting at path
+    fn symlink_chain(path: &Path, file_system: &dyn FileSystem) -> Vec<(PathBuf, PathBuf)> {
+        // Same limit as Linux ELOOP
+        const MAX_HOPS: usize = 40;
+        
+        let mut chain = Vec::new();
+        let mut current = path.to_path_buf();
+        
+        while chain.len() < MAX_HOPS {
+            let Ok(target) = file_system.read_link(&current) else {
+                break;
+            };
+            // Relative targets are relative to the link's directory
+            let next = current.parent().unwrap_or_else(|| Path::new(".")).join(&target);
+            chain.push((current, target));
+            current = next;
+        }
+        
+        chain
+    }
+    
+    /// Suggest include path for near-miss file names in searched directories
+    fn suggest_include_path(
+        directive: &IncludeDirective,
+        candidates: &[SearchCandidate],
+        config: &ProcessingConfig,
+    ) -> Option<String> {
+        let wanted = Path::new(&directive.file_path).file_name()?.to_str()?;
+        // Allow roughly one typo per three characters
+        let max_distance = (wanted.chars().count() / 3).max(1);
+        
+        let (_, best) = candidates.iter()
+            .filter_map(|candidate| config.file_system.read_dir(candidate.path.parent()?).ok())
+            .flatten()
+            .filter(|entry| config.file_system.is_file(entry))
+            .filter_map(|entry| entry.file_name()?.to_str().map(str::to_string))
+            .map(|name| (edit_distance(wanted, &name), name))
+            .filter(|(distance, _)| *distance <= max_distance)
+            .min()?;
+        
+        // Keep the directory part the user wrote
+        let suggestion = match directive.file_path.rfind('/') {
+            Some(index) => format!("{}{}", &directive.file_path[..=index], best),
+            None => best,
+        };
+        Some(suggestion)
+    }
+    
+    /// Candidate paths for include directive, in search order
+    ///
+    /// Quoted and bare paths search the including file's directory, then
+    /// include paths (`-I`), then library paths (`SHRUP_PATH`). `<file>`
+    /// searches include paths and library paths before the including
+    /// file's directory, as in C.
+    pub fn candidate_paths(directive: &IncludeDirective, config: &ProcessingConfig) -> Vec<SearchCandidate> {
+        let include_path = Path::new(&directive.file_path);
+        
+        // Absolute path: resolve relative to base directory
+        if include_path.is_absolute() {
+            return vec![SearchCandidate {
+                path: config.base_directory.join(include_path.strip_prefix("/").unwrap_or(include_path)),
+                search_dir: config.base_directory.clone(),
+                origin: SearchOrigin::BaseDirectory,
+            }];
+        }
+        
+        // Relative path: resolve relative to source file directory or search paths
+        let source_dir = directive.source_file
+            .parent()
+            .unwrap_or_else(|| Path::new("."));
+        let local = std::iter::once((source_dir, SearchOrigin::IncludingFile));
+        let search_paths = config.include_paths.iter()
+            .map(|dir| (dir.as_path(), SearchOrigin::IncludePath))
+            .chain(config.library_paths.iter().map(|dir| (dir.as_path(), SearchOrigin::LibraryPath)));
+        
+        let search_dirs: Vec<(&Path, SearchOrigin)> = match directive.quote_type {
+            IncludeQuoteType::AngleBrackets => search_paths.chain(local).collect(),
+            _ => local.chain(search_paths).collect(),
+        };
+        
+        search_dirs.into_iter()
+            .map(|(dir, origin)| SearchCandidate {
+                path: dir.join(include_path),
+                search_dir: dir.to_path_buf(),
+                origin,
+            })
+            .collect()
+    }
+    
+    /// Read library paths from `SHRUP_PATH`
+    pub fn library_paths_from_env() -> Vec<PathBuf> {
+        std::env::var_os(LIBRARY_PATH_ENV)
+            .map(|value| Self::parse_library_paths(&value))
+            .unwrap_or_default()
+    }
+    
+    /// Split path list (colon-separated on Unix), skipping empty entries
+    pub fn parse_library_paths(value: &OsStr) -> Vec<PathBuf> {
+        std::env::split_paths(value)
+            .filter(|path| !path.as_os_str().is_empty())
+            .collect()
     }
     
     /// Read file content with error handling
-    pub fn read_file_content(file_path: &Path) -> Result<String> {
-        fs::read_to_string(file_path)
+    pub fn read_file_content(file_path: &Path, config: &ProcessingConfig) -> Result<String> {
+        let bytes = config.file_system.read(file_path)
             .with_context(|| format!("Failed to read file: {}", file_path.display()))
             .map_err(|e| {
                 // Check specific error types
@@ -177,7 +801,47 @@ impl FileResolver {
                     }
                 }
                 e
-            })
+            })?;
+        
+        Self::decode_content(&bytes, file_path, config.input_encoding)
+    }
+    
+    /// Decode file bytes to UTF-8, dropping any BOM
+    ///
+    /// Encoding is chosen per file: BOM first, then a `coding:` comment in
+    /// the first two lines (`# -*- coding: shift_jis -*-`), then `encoding`.
+    pub fn decode_content(bytes: &[u8], file_path: &Path, encoding: InputEncoding) -> Result<String> {
+        let (encoding, bom_length) = Encoding::for_bom(bytes).unwrap_or_else(|| {
+            let fallback = match encoding {
+                InputEncoding::Auto => UTF_8,
+                InputEncoding::Explicit(encoding) => encoding,
+            };
+            (Self::declared_encoding(bytes).unwrap_or(fallback), 0)
+        });
+        
+        let (content, had_errors) = encoding.decode_without_bom_handling(&bytes[bom_length..]);
+        if had_errors {
+            return Err(PreprocessorError::InvalidEncoding {
+                path: file_path.to_path_buf(),
+                encoding: encoding.name().to_string(),
+            }.into());
+        }
+        
+        Ok(content.into_owned())
+    }
+    
+    /// Find encoding declared by `coding:` / `coding=` comment in the first two lines
+    fn declared_encoding(bytes: &[u8]) -> Option<&'static Encoding> {
+        bytes.split(|&byte| byte == b'\n').take(2).find_map(|line| {
+            let line = String::from_utf8_lossy(line);
+            let (_, rest) = line.trim_start().strip_prefix('#')?.split_once("coding")?;
+            let label: String = rest.strip_prefix([':', '='])?
+                .trim_start()
+                .chars()
+                .take_while(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+                .collect();
+            Encoding::for_label(label.as_bytes())
+        })
     }
     
     /// Generate debug comment for include
@@ -189,6 +853,28 @@ impl FileResolver {
             format!("# --- End of {} ---", display_path)
         }
     }
+    
+    /// Generate debug comment for skipped duplicate include
+    pub fn generate_skip_comment(file_path: &Path) -> String {
+        format!("# --- Skipped {} (already included) ---", file_path.display())
+    }
+}
+
+/// Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + (a_char != *b_char) as usize;
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    
+    previous[b.len()]
 }
 
 #[cfg(test)]
@@ -199,8 +885,10 @@ mod tests {
     
     #[test]
     fn test_processing_context_max_depth() {
-        let mut config = ProcessingConfig::default();
-        config.max_include_depth = 2;
+        let config = ProcessingConfig {
+            max_include_depth: 2,
+            ..Default::default()
+        };
         
         let mut context = ProcessingContext::new(config);
         let temp_dir = TempDir::new().unwrap();
@@ -208,8 +896,46 @@ mod tests {
         fs::write(&file_path, "content").unwrap();
         
         // Should succeed for first two files
-        assert!(context.enter_file(&file_path).is_ok());
-        assert!(context.enter_file(&file_path).is_err()); // Circular dependency
+        assert!(context.enter_file(&file_path, None).is_ok());
+        assert!(context.enter_file(&file_path, None).is_err()); // Circular dependency
+    }
+    
+    #[test]
+    fn test_processing_context_reenter_after_exit() {
+        let mut context = ProcessingContext::new(ProcessingConfig::default());
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("log.sh");
+        fs::write(&file_path, "content").unwrap();
+        
+        // Visited but no longer on the stack: not a cycle
+        context.enter_file(&file_path, None).unwrap();
+        context.exit_file();
+        assert!(context.should_include(&file_path, false).unwrap());
+        assert!(context.enter_file(&file_path, None).is_ok());
+    }
+    
+    #[test]
+    fn test_duplicate_include_policies() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("log.sh");
+        fs::write(&file_path, "content").unwrap();
+        
+        for (policy, expected) in [
+            (DuplicateIncludePolicy::Always, Some(true)),
+            (DuplicateIncludePolicy::Once, Some(false)),
+            (DuplicateIncludePolicy::Error, None),
+        ] {
+            let config = ProcessingConfig {
+                duplicate_includes: policy,
+                ..Default::default()
+            };
+            let mut context = ProcessingContext::new(config);
+            context.enter_file(&file_path, None).unwrap();
+            context.exit_file();
+            
+            assert_eq!(context.should_include(&file_path, false).ok(), expected);
+            assert_eq!(context.should_include(&file_path, true).ok(), Some(false));
+        }
     }
     
     #[test]
@@ -236,4 +962,229 @@ mod tests {
         let resolved = FileResolver::resolve_include_path(&directive, &config).unwrap();
         assert_eq!(resolved, include_file);
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn test_resolve_include_paths_by_quote_type() {
+        let temp_dir = TempDir::new().unwrap();
+        let lib_dir = temp_dir.path().join("lib");
+        let vendor_dir = temp_dir.path().join("vendor");
+        fs::create_dir(&lib_dir).unwrap();
+        fs::create_dir(&vendor_dir).unwrap();
+        
+        let source_file = temp_dir.path().join("main.sh");
+        fs::write(&source_file, "").unwrap();
+        fs::write(temp_dir.path().join("log.sh"), "").unwrap();
+        fs::write(lib_dir.join("log.sh"), "").unwrap();
+        fs::write(vendor_dir.join("json.sh"), "").unwrap();
+        
+        let config = ProcessingConfig {
+            base_directory: temp_dir.path().to_path_buf(),
+            include_paths: vec![lib_dir.clone()],
+            library_paths: vec![vendor_dir.clone()],
+            ..Default::default()
+        };
+        let resolve = |file_path: &str, quote_type| {
+            let directive = IncludeDirective::new(1, file_path.to_string(), source_file.clone(), quote_type);
+            FileResolver::resolve_include_path(&directive, &config)
+        };
+        
+        // Angle brackets prefer include paths, quotes prefer the including file's directory
+        assert_eq!(resolve("log.sh", IncludeQuoteType::AngleBrackets).unwrap(), lib_dir.join("log.sh"));
+        assert_eq!(resolve("log.sh", IncludeQuoteType::DoubleQuotes).unwrap(), temp_dir.path().join("log.sh"));
+        
+        // Both fall back to the other location
+        assert_eq!(resolve("json.sh", IncludeQuoteType::DoubleQuotes).unwrap(), vendor_dir.join("json.sh"));
+        assert_eq!(resolve("json.sh", IncludeQuoteType::AngleBrackets).unwrap(), vendor_dir.join("json.sh"));
+        
+        // Errors list every candidate in search order
+        let err = resolve("missing.sh", IncludeQuoteType::AngleBrackets).unwrap_err();
+        match err.downcast_ref::<PreprocessorError>() {
+            Some(PreprocessorError::IncludeNotFound { searched, suggestion, .. }) => {
+                assert_eq!(suggestion, &None);
+                let origins: Vec<_> = searched.iter().map(|candidate| candidate.origin).collect();
+                assert_eq!(origins, vec![
+                    SearchOrigin::IncludePath,
+                    SearchOrigin::LibraryPath,
+                    SearchOrigin::IncludingFile,
+                ]);
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+    
+    #[test]
+    fn test_include_not_found_suggestion() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir(temp_dir.path().join("lib")).unwrap();
+        fs::write(temp_dir.path().join("lib/logging.sh"), "").unwrap();
+        
+        let source_file = temp_dir.path().join("main.sh");
+        let directive = IncludeDirective::new(
+            7,
+            "lib/loging.sh".to_string(),
+            source_file.clone(),
+            crate::parser::IncludeQuoteType::DoubleQuotes,
+        );
+        
+        let err = FileResolver::resolve_include_path(&directive, &ProcessingConfig::default()).unwrap_err();
+        match err.downcast_ref::<PreprocessorError>() {
+            Some(PreprocessorError::IncludeNotFound { suggestion, line_number, including_file, .. }) => {
+                assert_eq!(suggestion.as_deref(), Some("lib/logging.sh"));
+                assert_eq!(*line_number, 7);
+                assert_eq!(including_file, &source_file);
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+        
+        let message = err.to_string();
+        assert!(message.contains(":7: #include \"lib/loging.sh\""));
+        assert!(message.contains("[including file]"));
+        assert!(message.contains("did you mean: lib/logging.sh?"));
+    }
+    
+    #[test]
+    fn test_include_outside_root() {
+        let temp_dir = TempDir::new().unwrap();
+        let project_dir = temp_dir.path().join("project");
+        let lib_dir = temp_dir.path().join("lib");
+        fs::create_dir(&project_dir).unwrap();
+        fs::create_dir(&lib_dir).unwrap();
+        fs::write(temp_dir.path().join("secret.sh"), "").unwrap();
+        fs::write(lib_dir.join("log.sh"), "").unwrap();
+        
+        let source_file = project_dir.join("main.sh");
+        let resolve = |file_path: &str, config: &ProcessingConfig| {
+            let directive = IncludeDirective::new(
+                1,
+                file_path.to_string(),
+                source_file.clone(),
+                crate::parser::IncludeQuoteType::DoubleQuotes,
+            );
+            FileResolver::resolve_include_path(&directive, config)
+        };
+        
+        let config = ProcessingConfig {
+            base_directory: project_dir.clone(),
+            include_paths: vec![lib_dir.clone()],
+            ..Default::default()
+        };
+        
+        for file_path in ["../secret.sh", "/../secret.sh", "../lib/log.sh"] {
+            let err = resolve(file_path, &config).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<PreprocessorError>(),
+                Some(PreprocessorError::PathOutsideRoot { .. })
+            ), "{} should be rejected", file_path);
+        }
+        
+        // Search directories are roots for files found through them
+        assert!(resolve("log.sh", &config).is_ok());
+        
+        // Wider root and opt-out
+        let wider = ProcessingConfig {
+            root_directory: Some(temp_dir.path().to_path_buf()),
+            ..config.clone()
+        };
+        assert!(resolve("../secret.sh", &wider).is_ok());
+        
+        let trusted = ProcessingConfig {
+            allow_outside_root: true,
+            ..config
+        };
+        assert!(resolve("../secret.sh", &trusted).is_ok());
+    }
+    
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_policies() {
+        use std::os::unix::fs::symlink;
+        
+        let temp_dir = TempDir::new().unwrap();
+        let project_dir = temp_dir.path().join("project");
+        let vendor_dir = temp_dir.path().join("vendor");
+        fs::create_dir_all(project_dir.join("lib")).unwrap();
+        fs::create_dir(&vendor_dir).unwrap();
+        fs::write(vendor_dir.join("json.sh"), "").unwrap();
+        fs::write(project_dir.join("lib/log.sh"), "").unwrap();
+        
+        // Escaping directory link and in-root file link
+        symlink(&vendor_dir, project_dir.join("vendor")).unwrap();
+        symlink("lib/log.sh", project_dir.join("log.sh")).unwrap();
+        
+        let source_file = project_dir.join("main.sh");
+        let resolve = |file_path: &str, symlinks, allow_outside_root| {
+            let config = ProcessingConfig {
+                base_directory: project_dir.clone(),
+                symlinks,
+                allow_outside_root,
+                ..Default::default()
+            };
+            let directive = IncludeDirective::new(
+                1,
+                file_path.to_string(),
+                source_file.clone(),
+                crate::parser::IncludeQuoteType::DoubleQuotes,
+            );
+            FileResolver::resolve_include_path(&directive, &config)
+        };
+        let refused = |result: Result<PathBuf>| match result {
+            Err(err) => match err.downcast_ref::<PreprocessorError>() {
+                Some(PreprocessorError::SymlinkRefused { chain, .. }) => chain.len(),
+                _ => panic!("unexpected error: {}", err),
+            },
+            Ok(path) => panic!("unexpectedly resolved: {}", path.display()),
+        };
+        
+        assert!(resolve("log.sh", SymlinkPolicy::Follow, false).is_ok());
+        assert!(resolve("vendor/json.sh", SymlinkPolicy::Follow, true).is_ok());
+        
+        assert_eq!(refused(resolve("log.sh", SymlinkPolicy::Refuse, false)), 1);
+        assert_eq!(refused(resolve("vendor/json.sh", SymlinkPolicy::Refuse, true)), 1);
+        
+        assert!(resolve("log.sh", SymlinkPolicy::RefuseEscaping, false).is_ok());
+        assert_eq!(refused(resolve("vendor/json.sh", SymlinkPolicy::RefuseEscaping, true)), 1);
+    }
+    
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("log.sh", "log.sh"), 0);
+        assert_eq!(edit_distance("lgo.sh", "log.sh"), 2);
+        assert_eq!(edit_distance("loging.sh", "logging.sh"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+    
+    #[cfg(unix)]
+    #[test]
+    fn test_parse_library_paths() {
+        let paths = FileResolver::parse_library_paths(OsStr::new("/usr/share/shrup::/home/me/.local/share/shrup"));
+        assert_eq!(paths, vec![
+            PathBuf::from("/usr/share/shrup"),
+            PathBuf::from("/home/me/.local/share/shrup"),
+        ]);
+    }
+    
+    #[test]
+    fn test_decode_content() {
+        let path = Path::new("legacy.sh");
+        let decode = |bytes: &[u8], encoding| FileResolver::decode_content(bytes, path, encoding);
+        
+        // BOM is dropped
+        assert_eq!(decode(b"\xEF\xBB\xBFecho ok\n", InputEncoding::Auto).unwrap(), "echo ok\n");
+        assert_eq!(decode(b"\xFF\xFEo\x00k\x00", InputEncoding::Auto).unwrap(), "ok");
+        
+        // Non-UTF-8 without declaration fails in auto mode
+        let latin1 = b"echo caf\xE9\n";
+        let err = decode(latin1, InputEncoding::Auto).unwrap_err();
+        assert!(err.to_string().contains("as UTF-8"));
+        let latin1_encoding = "latin1".parse::<InputEncoding>().unwrap();
+        assert_eq!(decode(latin1, latin1_encoding).unwrap(), "echo caf\u{e9}\n");
+        
+        // Per-file `coding:` comment takes precedence over the default encoding
+        let sjis = b"#!/bin/sh\n# -*- coding: shift_jis -*-\necho \x82\xA0\n";
+        assert_eq!(decode(sjis, InputEncoding::Auto).unwrap(), "#!/bin/sh\n# -*- coding: shift_jis -*-\necho \u{3042}\n");
+        assert_eq!(decode(sjis, latin1_encoding).unwrap(), "#!/bin/sh\n# -*- coding: shift_jis -*-\necho \u{3042}\n");
+        
+        // Declaration after the second line is ignored
+        assert!(decode(b"#!/bin/sh\n\n# coding=latin1\necho \xE9\n", InputEncoding::Auto).is_err());
+    }
+}
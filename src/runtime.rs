@@ -0,0 +1,135 @@
+//! Runtime line-origin table embedded in bundled scripts
+
+use crate::sourcemap::{LineOrigin, MappedContent};
+
+/// Insert line-origin table and lookup helpers after the shebang
+///
+/// The bundle can then report source locations itself, e.g.
+/// `trap '__shrup_report_error $? $LINENO' ERR` prints `lib/net.sh:42`.
+/// Helpers are POSIX sh but need `$LINENO` (bash, ksh, zsh, busybox; not
+/// dash). Prelude lines are generated lines.
+pub fn insert_origin_table(content: MappedContent, sources: &[String]) -> MappedContent {
+    // Keep shebang as first line
+    let header_lines = usize::from(content.text.starts_with("#!"));
+    let header_length = content.text.split_inclusive('\n').take(header_lines).map(str::len).sum();
+    
+    let mut result = MappedContent::new();
+    result.text.push_str(&content.text[..header_length]);
+    result.origins.extend(&content.origins[..header_lines]);
+    result.terminate("\n");
+    
+    let body_origins = &content.origins[header_lines..];
+    for line in prelude(&origin_runs(body_origins, sources)).lines() {
+        result.push_line(line, "\n", None);
+    }
+    
+    result.text.push_str(&content.text[header_length..]);
+    result.origins.extend(body_origins);
+    result
+}
+
+/// Runs of consecutive lines as `start source_line path` (source line 0 for generated lines)
+fn origin_runs(origins: &[Option<LineOrigin>], sources: &[String]) -> Vec<String> {
+    let mut runs = Vec::new();
+    let mut previous: Option<Option<LineOrigin>> = None;
+    
+    for (index, origin) in origins.iter().enumerate() {
+        let continues = match (previous, origin) {
+            (Some(None), None) => true,
+            (Some(Some(previous)), Some(origin)) => {
+                previous.source == origin.source && previous.line + 1 == origin.line
+            }
+            _ => false,
+        };
+        
+        if !continues {
+            let run = match origin {
+                Some(origin) => format!("{} {} {}", index + 1, origin.line, sources[origin.source]),
+                None => format!("{} 0 -", index + 1),
+            };
+            runs.push(run);
+        }
+        previous = Some(*origin);
+    }
+    
+    runs
+}
+
+/// Shell code defining the origin table and helpers
+fn prelude(runs: &[String]) -> String {
+    // Table is single-quoted
+    let table = runs.join("\n").replace('\'', r"'\''");
+    
+    format!(r#"# --- shrup runtime line origins ---
+__shrup_origins='{table}'
+# Print source file:line of bundle line (`__shrup_origin "$LINENO"`)
+__shrup_origin() {{
+    __shrup_location="$1"
+    __shrup_offset=$(($1 - __shrup_base))
+    while read -r __shrup_start __shrup_line __shrup_source; do
+        [ "$__shrup_start" -gt "$__shrup_offset" ] && break
+        if [ "$__shrup_line" -gt 0 ]; then
+            __shrup_location="$__shrup_source:$((__shrup_line + __shrup_offset - __shrup_start))"
+        else
+            __shrup_location="$1"
+        fi
+    done <<__SHRUP_ORIGINS__
+$__shrup_origins
+__SHRUP_ORIGINS__
+    echo "$__shrup_location"
+}}
+# Report failed command (`trap '__shrup_report_error $? $LINENO' ERR`)
+__shrup_report_error() {{
+    echo "$(__shrup_origin "$2"): command failed with exit status $1" >&2
+}}
+# --- end of shrup runtime line origins (bundle lines start below) ---
+__shrup_base=$LINENO
+"#)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn origin(source: usize, line: usize) -> Option<LineOrigin> {
+        Some(LineOrigin { source, line })
+    }
+    
+    #[test]
+    fn test_origin_runs() {
+        let sources = ["main.sh".to_string(), "lib/it's.sh".to_string()];
+        let origins = [origin(0, 2), origin(1, 1), origin(1, 2), None, None, origin(1, 5), origin(0, 4)];
+        
+        assert_eq!(origin_runs(&origins, &sources), [
+            "1 2 main.sh",
+            "2 1 lib/it's.sh",
+            "4 0 -",
+            "6 5 lib/it's.sh",
+            "7 4 main.sh",
+        ]);
+        assert!(prelude(&origin_runs(&origins, &sources)).contains(r"2 1 lib/it'\''s.sh"));
+    }
+    
+    #[test]
+    fn test_insert_origin_table() {
+        let content = MappedContent::verbatim("#!/bin/sh\necho a\necho b", 0);
+        let sources = ["main.sh".to_string()];
+        
+        let result = insert_origin_table(content, &sources);
+        assert_eq!(result.text.split_inclusive('\n').count(), result.origins.len());
+        assert!(result.text.starts_with("#!/bin/sh\n# --- shrup runtime line origins ---\n__shrup_origins='1 2 main.sh'\n"));
+        assert!(result.text.ends_with("\n__shrup_base=$LINENO\necho a\necho b"));
+        assert_eq!(result.origins[0], origin(0, 1));
+        assert_eq!(result.origins[1], None);
+        assert_eq!(result.origins[result.origins.len() - 1], origin(0, 3));
+        
+        // Without shebang the table comes first
+        let result = insert_origin_table(MappedContent::verbatim("echo a\n", 0), &sources);
+        assert!(result.text.starts_with("# --- shrup runtime line origins ---\n"));
+        
+        // Unterminated shebang line is terminated before the table
+        let result = insert_origin_table(MappedContent::verbatim("#!/bin/sh", 0), &sources);
+        assert!(result.text.starts_with("#!/bin/sh\n# --- shrup runtime line origins ---\n"));
+        assert_eq!(result.text.split_inclusive('\n').count(), result.origins.len());
+    }
+}
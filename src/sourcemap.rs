@@ -0,0 +1,181 @@
+//! Source maps from output lines back to source file lines
+
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+
+/// Source map format version
+pub const SOURCE_MAP_VERSION: u32 = 1;
+
+/// Source location of an output line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "(usize, usize)", into = "(usize, usize)")]
+pub struct LineOrigin {
+    /// Index into the source map's `sources`
+    pub source: usize,
+    /// Line number in the source file (1-based)
+    pub line: usize,
+}
+
+impl From<(usize, usize)> for LineOrigin {
+    fn from((source, line): (usize, usize)) -> Self {
+        Self { source, line }
+    }
+}
+
+impl From<LineOrigin> for (usize, usize) {
+    fn from(origin: LineOrigin) -> Self {
+        (origin.source, origin.line)
+    }
+}
+
+/// Processed text with the origin of each line
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MappedContent {
+    /// Output text
+    pub text: String,
+    /// Origin per output line (`None` for generated lines such as debug comments)
+    pub origins: Vec<Option<LineOrigin>>,
+}
+
+impl MappedContent {
+    /// Create empty content
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Content kept verbatim from a single source
+    pub fn verbatim(text: &str, source: usize) -> Self {
+        let origins = (1..=text.split_inclusive('\n').count())
+            .map(|line| Some(LineOrigin { source, line }))
+            .collect();
+        Self { text: text.to_string(), origins }
+    }
+    
+    /// Append line with its line ending
+    pub fn push_line(&mut self, text: &str, ending: &str, origin: Option<LineOrigin>) {
+        self.text.push_str(text);
+        self.text.push_str(ending);
+        self.origins.push(origin);
+    }
+    
+    /// Append other content (must start on a new line)
+    pub fn append(&mut self, other: MappedContent) {
+        self.text.push_str(&other.text);
+        self.origins.extend(other.origins);
+    }
+    
+    /// Terminate unterminated last line with line ending
+    pub fn terminate(&mut self, ending: &str) {
+        if !self.text.is_empty() && !self.text.ends_with('\n') {
+            self.text.push_str(ending);
+        }
+    }
+}
+
+/// Map from output lines to source file lines (`output.sh.map`)
+///
+/// `lines[i]` is `[source index, source line]` of output line `i + 1`, or
+/// `null` for generated lines.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SourceMap {
+    /// Format version
+    pub version: u32,
+    /// Output file name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    /// Source file paths (relative to base directory when inside it)
+    pub sources: Vec<String>,
+    /// Origin per output line
+    pub lines: Vec<Option<LineOrigin>>,
+}
+
+impl SourceMap {
+    /// Create source map
+    pub fn new(file: Option<String>, sources: Vec<String>, lines: Vec<Option<LineOrigin>>) -> Self {
+        Self { version: SOURCE_MAP_VERSION, file, sources, lines }
+    }
+    
+    /// Sidecar path for output file (`output.sh` -> `output.sh.map`)
+    pub fn path_for(output_path: &Path) -> PathBuf {
+        let mut path = output_path.as_os_str().to_os_string();
+        path.push(".map");
+        PathBuf::from(path)
+    }
+    
+    /// Source file and line of output line (1-based)
+    pub fn lookup(&self, output_line: usize) -> Option<(&str, usize)> {
+        let origin = (*self.lines.get(output_line.checked_sub(1)?)?)?;
+        let source = self.sources.get(origin.source)?;
+        Some((source.as_str(), origin.line))
+    }
+    
+    /// Serialize to JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("source map serialization cannot fail")
+    }
+    
+    /// Parse from JSON
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let source_map: Self = serde_json::from_str(json)
+            .map_err(|e| format!("invalid source map: {}", e))?;
+        
+        if source_map.version != SOURCE_MAP_VERSION {
+            return Err(format!("unsupported source map version {}", source_map.version));
+        }
+        Ok(source_map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_mapped_content() {
+        let mut content = MappedContent::new();
+        content.push_line("#!/bin/sh", "\n", Some(LineOrigin { source: 0, line: 1 }));
+        
+        let mut included = MappedContent::verbatim("echo a\necho b", 1);
+        included.terminate("\n");
+        content.append(included);
+        content.push_line("# generated", "", None);
+        
+        assert_eq!(content.text, "#!/bin/sh\necho a\necho b\n# generated");
+        assert_eq!(content.origins, vec![
+            Some(LineOrigin { source: 0, line: 1 }),
+            Some(LineOrigin { source: 1, line: 1 }),
+            Some(LineOrigin { source: 1, line: 2 }),
+            None,
+        ]);
+    }
+    
+    #[test]
+    fn test_source_map_json() {
+        let source_map = SourceMap::new(
+            Some("output.sh".to_string()),
+            vec!["main.sh".to_string(), "lib/net.sh".to_string()],
+            vec![Some(LineOrigin { source: 0, line: 1 }), None, Some(LineOrigin { source: 1, line: 42 })],
+        );
+        
+        let json = source_map.to_json();
+        assert_eq!(
+            json,
+            r#"{"version":1,"file":"output.sh","sources":["main.sh","lib/net.sh"],"lines":[[0,1],null,[1,42]]}"#
+        );
+        assert_eq!(SourceMap::from_json(&json).unwrap(), source_map);
+        
+        assert_eq!(source_map.lookup(1), Some(("main.sh", 1)));
+        assert_eq!(source_map.lookup(2), None);
+        assert_eq!(source_map.lookup(3), Some(("lib/net.sh", 42)));
+        assert_eq!(source_map.lookup(0), None);
+        assert_eq!(source_map.lookup(4), None);
+        
+        assert!(SourceMap::from_json(r#"{"version":2,"sources":[],"lines":[]}"#).is_err());
+        assert!(SourceMap::from_json("not json").is_err());
+    }
+    
+    #[test]
+    fn test_source_map_path() {
+        assert_eq!(SourceMap::path_for(Path::new("dist/deploy.sh")), PathBuf::from("dist/deploy.sh.map"));
+    }
+}
@@ -0,0 +1,198 @@
+//! Remapping of bundle line references in shell output through a source map
+
+use crate::sourcemap::SourceMap;
+
+/// Rewrites `script.sh: line N:` and `set -x` line references to source `file:line`
+#[derive(Debug, Clone)]
+pub struct TraceRemapper {
+    /// Source map of the bundled script
+    source_map: SourceMap,
+    /// Script name as it appears in shell output (`$0`, `BASH_SOURCE`)
+    script_name: String,
+    /// Rewrite bare `$LINENO` trace prefixes (`PS4='+${LINENO}: '`)
+    lineno_prefix: bool,
+}
+
+impl TraceRemapper {
+    /// Create remapper for script name (`deploy.sh`, or `bash` for piped scripts)
+    pub fn new(source_map: SourceMap, script_name: impl Into<String>) -> Self {
+        Self { source_map, script_name: script_name.into(), lineno_prefix: false }
+    }
+    
+    /// Rewrite `++12: foo` traces of `PS4='+${LINENO}: '`
+    ///
+    /// Off by default: ordinary output such as `+1 passed` has the same shape.
+    pub fn lineno_prefix(mut self, enabled: bool) -> Self {
+        self.lineno_prefix = enabled;
+        self
+    }
+    
+    /// Remap every line of text
+    pub fn remap(&self, text: &str) -> String {
+        text.split_inclusive('\n')
+            .map(|line| self.remap_line(line))
+            .collect()
+    }
+    
+    /// Remap references in a single line
+    ///
+    /// Handles bash errors (`./deploy.sh: line 12: foo: command not found`),
+    /// `PS4='+ ${BASH_SOURCE}:${LINENO}: '` traces (`+ ./deploy.sh:12: foo`)
+    /// and, if enabled, `PS4='+${LINENO}: '` traces (`++12: foo`).
+    /// References to generated or unknown lines are kept as is.
+    pub fn remap_line(&self, line: &str) -> String {
+        let line = self.remap_script_references(line);
+        if !self.lineno_prefix {
+            return line;
+        }
+        self.remap_trace_prefix(&line).unwrap_or(line)
+    }
+    
+    /// Rewrite `SCRIPT: line N` and `SCRIPT:N` references
+    fn remap_script_references(&self, line: &str) -> String {
+        let mut result = String::with_capacity(line.len());
+        let mut rest = line;
+        
+        while let Some(index) = self.find_script_name(rest) {
+            let after_name = &rest[index + self.script_name.len()..];
+            let token_start = rest[..index].rfind(is_token_separator).map_or(0, |i| i + 1);
+            
+            // `: line N` (bash errors) or `:N` (BASH_SOURCE:LINENO)
+            let (separator, after_separator) = if let Some(after) = after_name.strip_prefix(": line ") {
+                (": line ", after)
+            } else if let Some(after) = after_name.strip_prefix(':') {
+                (":", after)
+            } else {
+                ("", after_name)
+            };
+            let digits = after_separator.len() - after_separator.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            let location = after_separator[..digits].parse().ok()
+                .and_then(|output_line| self.source_map.lookup(output_line));
+            
+            match (separator, location) {
+                ("", _) | (_, None) => {
+                    result.push_str(&rest[..index + self.script_name.len()]);
+                    rest = after_name;
+                }
+                (separator, Some((source, source_line))) => {
+                    result.push_str(&rest[..token_start]);
+                    result.push_str(source);
+                    result.push_str(separator);
+                    result.push_str(&source_line.to_string());
+                    rest = &after_separator[digits..];
+                }
+            }
+        }
+        
+        result.push_str(rest);
+        result
+    }
+    
+    /// Find script name at a path component boundary (`./dist/deploy.sh`, not `predeploy.sh`)
+    fn find_script_name(&self, text: &str) -> Option<usize> {
+        if self.script_name.is_empty() {
+            return None;
+        }
+        
+        text.match_indices(&self.script_name)
+            .map(|(index, _)| index)
+            .find(|&index| text[..index].chars().next_back().is_none_or(|c| c == '/' || is_token_separator(c)))
+    }
+    
+    /// Rewrite bare `$LINENO` after `set -x` prefix (`++12: foo` -> `++lib/net.sh:42: foo`)
+    ///
+    /// Only the exact `+<digits>: ` shape is rewritten.
+    fn remap_trace_prefix(&self, line: &str) -> Option<String> {
+        let number = line.trim_start_matches('+');
+        if number.len() == line.len() {
+            return None;
+        }
+        
+        let digits = number.len() - number.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        if digits == 0 || !number[digits..].starts_with(": ") {
+            return None;
+        }
+        
+        let (source, source_line) = self.source_map.lookup(number[..digits].parse().ok()?)?;
+        let prefix_length = line.len() - number.len();
+        Some(format!("{}{}:{}{}", &line[..prefix_length], source, source_line, &number[digits..]))
+    }
+}
+
+/// Character ending a path token in shell output
+fn is_token_separator(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '\'' | '"' | '(' | ')' | '[' | ']' | '`' | ',' | '=')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sourcemap::LineOrigin;
+    
+    fn remapper(script_name: &str) -> TraceRemapper {
+        let source_map = SourceMap::new(
+            Some("deploy.sh".to_string()),
+            vec!["main.sh".to_string(), "lib/net.sh".to_string()],
+            vec![
+                Some(LineOrigin { source: 0, line: 1 }),
+                None,
+                Some(LineOrigin { source: 1, line: 42 }),
+                Some(LineOrigin { source: 0, line: 7 }),
+            ],
+        );
+        TraceRemapper::new(source_map, script_name)
+    }
+    
+    #[test]
+    fn test_remap_bash_errors() {
+        let remapper = remapper("deploy.sh");
+        
+        assert_eq!(
+            remapper.remap_line("./dist/deploy.sh: line 3: curl: command not found"),
+            "lib/net.sh: line 42: curl: command not found"
+        );
+        assert_eq!(
+            remapper.remap_line("/opt/deploy.sh: line 4: syntax error near unexpected token `fi'"),
+            "main.sh: line 7: syntax error near unexpected token `fi'"
+        );
+        
+        // Generated, out-of-range and unrelated references are kept
+        assert_eq!(remapper.remap_line("deploy.sh: line 2: oops"), "deploy.sh: line 2: oops");
+        assert_eq!(remapper.remap_line("deploy.sh: line 99: oops"), "deploy.sh: line 99: oops");
+        assert_eq!(remapper.remap_line("predeploy.sh: line 3: oops"), "predeploy.sh: line 3: oops");
+        assert_eq!(remapper.remap_line("deploy.sh is done"), "deploy.sh is done");
+        
+        // Piped scripts are reported as `bash`
+        let remapper = self::remapper("bash");
+        assert_eq!(remapper.remap_line("bash: line 3: x: unbound variable\n"), "lib/net.sh: line 42: x: unbound variable\n");
+    }
+    
+    #[test]
+    fn test_remap_xtrace() {
+        let remapper = remapper("deploy.sh");
+        
+        // PS4='+ ${BASH_SOURCE}:${LINENO}: '
+        assert_eq!(remapper.remap_line("++ ./deploy.sh:3: curl example.com"), "++ lib/net.sh:42: curl example.com");
+        // PS4='+${LINENO}: ' is opt-in
+        assert_eq!(remapper.remap_line("++3: curl example.com"), "++3: curl example.com");
+        let remapper = remapper.lineno_prefix(true);
+        assert_eq!(remapper.remap_line("++3: curl example.com"), "++lib/net.sh:42: curl example.com");
+        assert_eq!(remapper.remap_line("+ echo 3: not a line"), "+ echo 3: not a line");
+        
+        assert_eq!(
+            remapper.remap("+ deploy.sh:1: set -e\nok\n+ deploy.sh:4: exit 1\n"),
+            "+ main.sh:1: set -e\nok\n+ main.sh:7: exit 1\n"
+        );
+    }
+    
+    #[test]
+    fn test_keep_program_output() {
+        // Ordinary `+N ...` output is not a trace, even with `lineno_prefix`
+        for remapper in [remapper("deploy.sh"), remapper("deploy.sh").lineno_prefix(true)] {
+            assert_eq!(remapper.remap_line("+1 passed\n"), "+1 passed\n");
+            assert_eq!(remapper.remap_line("+ 3 files changed\n"), "+ 3 files changed\n");
+            assert_eq!(remapper.remap_line("+ 4: not a trace\n"), "+ 4: not a trace\n");
+            assert_eq!(remapper.remap_line("+3:00 elapsed\n"), "+3:00 elapsed\n");
+        }
+    }
+}